@@ -0,0 +1,250 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::{ColoredTLSHBuilder, TLSHError, TLSH};
+
+/// Largest merged bucket count after scaling to integers
+const SCALED_MAX: f64 = (1u64 << 40) as f64;
+
+/// Weight of inputs added without an explicit weight
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Weighting {
+    /// Every input has weight 1
+    #[default]
+    Equal,
+    /// The weight of an input grows with the logarithm of its length, `log2(1 + length)`
+    LogLength,
+}
+
+/// Options of merging inputs by `TLSHAggregator`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct AggregateOptions {
+    /// Scale the bucket counts of each input to the same total before weighting them
+    ///
+    /// Without normalization, inputs contribute in proportion to their length, so a few large
+    /// files dominate the hash of a package.
+    pub normalize: bool,
+    /// Weight of inputs added by `add`, `add_reader` and `add_file`
+    pub weighting: Weighting,
+}
+
+/// Calculates one TLSH hash of a collection of inputs, e.g. the files of a package
+///
+/// The bucket counts of the inputs are merged, so no window spans two inputs and the result does
+/// not depend on their order. The lvalue is that of the total length.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{AggregateOptions, TLSHAggregator, Weighting};
+/// let a = include_bytes!("../test/data/random.txt");
+/// let b = b"Hello, world!\n".repeat(40);
+/// let mut aggregator = TLSHAggregator::new(AggregateOptions {
+///     normalize: true,
+///     weighting: Weighting::Equal,
+/// });
+/// aggregator.add(a);
+/// aggregator.add(&b);
+/// assert!(aggregator.get_hash().is_ok());
+/// ```
+#[derive(Clone)]
+pub struct TLSHAggregator {
+    builder: ColoredTLSHBuilder,
+    options: AggregateOptions,
+    buckets: [f64; 256],
+    checksum: u8,
+    data_len: u64,
+}
+
+impl TLSHAggregator {
+    pub fn new(options: AggregateOptions) -> Self {
+        Self {
+            builder: ColoredTLSHBuilder::new(&[0]),
+            options,
+            buckets: [0.0; 256],
+            checksum: 0,
+            data_len: 0,
+        }
+    }
+
+    /// Add an input, weighted according to the options
+    pub fn add(&mut self, data: &[u8]) {
+        self.builder.reset();
+        self.builder.update(data);
+        self.merge(None);
+    }
+
+    /// Add an input with an explicit, non-negative weight
+    pub fn add_weighted(&mut self, data: &[u8], weight: f64) {
+        self.builder.reset();
+        self.builder.update(data);
+        self.merge(Some(weight));
+    }
+
+    /// Add all data of a reader as an input, weighted according to the options
+    ///
+    /// Returns the number of bytes read. Nothing is added if reading fails.
+    pub fn add_reader(&mut self, reader: impl Read) -> io::Result<u64> {
+        self.builder.reset();
+        let len = self.builder.update_from_reader(reader)?;
+        self.merge(None);
+        Ok(len)
+    }
+
+    /// Add the content of a file as an input, weighted according to the options
+    ///
+    /// Returns the number of bytes read. Nothing is added if reading fails.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.builder.reset();
+        let len = self.builder.update_from_file(path)?;
+        self.merge(None);
+        Ok(len)
+    }
+
+    fn merge(&mut self, weight: Option<f64>) {
+        let (buckets, checksum, data_len) = self.builder.color_state(0);
+        let mut weight = weight.unwrap_or(match self.options.weighting {
+            Weighting::Equal => 1.0,
+            Weighting::LogLength => (1.0 + data_len as f64).log2(),
+        });
+        assert!(weight >= 0.0 && weight.is_finite(), "Invalid weight");
+        let total: u64 = buckets.iter().sum();
+        if self.options.normalize && total > 0 {
+            weight /= total as f64;
+        }
+        for (merged, count) in self.buckets.iter_mut().zip(buckets) {
+            *merged += weight * *count as f64;
+        }
+        self.checksum = self.checksum.wrapping_add(checksum);
+        self.data_len += data_len;
+    }
+
+    /// Calculate the hash of the inputs added so far
+    ///
+    /// The merged counts are scaled by a power of two and quantized by `TLSH::from_buckets`, so a
+    /// single input with the default options gets the hash `TLSHBuilder` calculates.
+    pub fn get_hash(&self) -> Result<TLSH, TLSHError> {
+        let max = self.buckets.iter().cloned().fold(0.0, f64::max);
+        let scale = if max > 0.0 { (SCALED_MAX / max).log2().floor().exp2() } else { 0.0 };
+        let buckets = self.buckets.map(|count| (count * scale).round() as u64);
+        TLSH::from_buckets(&buckets, self.checksum, self.data_len)
+    }
+}
+
+impl Default for TLSHAggregator {
+    fn default() -> Self {
+        Self::new(AggregateOptions::default())
+    }
+}
+
+/// Calculate one TLSH hash of all regular files under a directory
+///
+/// The directory is walked recursively, symbolic links are not followed. The outer result
+/// reports I/O errors, the inner one the outcome of the hash calculation. See `TLSHAggregator`.
+pub fn hash_directory(path: impl AsRef<Path>, options: AggregateOptions) -> io::Result<Result<TLSH, TLSHError>> {
+    let mut aggregator = TLSHAggregator::new(options);
+    add_directory(&mut aggregator, path.as_ref())?;
+    Ok(aggregator.get_hash())
+}
+
+fn add_directory(aggregator: &mut TLSHAggregator, dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            add_directory(aggregator, &entry.path())?;
+        } else if file_type.is_file() {
+            aggregator.add_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::SplitMix64, TLSHBuilder};
+
+    #[test]
+    fn test_merge() {
+        let mut rng = SplitMix64::new(11);
+        let files = [rng.bytes(5000), rng.bytes(700), rng.bytes(40)];
+
+        for options in [
+            AggregateOptions::default(),
+            AggregateOptions { normalize: true, weighting: Weighting::Equal },
+            AggregateOptions { normalize: true, weighting: Weighting::LogLength },
+        ] {
+            let mut forward = TLSHAggregator::new(options);
+            let mut backward = TLSHAggregator::new(options);
+            for file in &files {
+                forward.add(file);
+            }
+            for file in files.iter().rev() {
+                backward.add_reader(file.as_slice()).unwrap();
+            }
+            let hash = forward.get_hash().unwrap();
+            assert_eq!(hash.to_digest(), backward.get_hash().unwrap().to_digest());
+            assert_eq!(hash.lvalue, crate::util::calc_lvalue(5740).unwrap());
+        }
+
+        // Normalized, the small file counts as much as the large one
+        let mut raw = TLSHAggregator::default();
+        let mut normalized = TLSHAggregator::new(AggregateOptions { normalize: true, ..Default::default() });
+        for aggregator in [&mut raw, &mut normalized] {
+            aggregator.add(&files[0]);
+            aggregator.add(&files[1]);
+        }
+        let mut small = TLSHAggregator::default();
+        small.add_weighted(&files[1], 1.0);
+        small.add_weighted(&files[0], 0.0);
+        let to_small = |a: &TLSHAggregator| TLSH::diff(&a.get_hash().unwrap(), &small.get_hash().unwrap());
+        assert!(to_small(&normalized) < to_small(&raw));
+
+        assert!(matches!(TLSHAggregator::default().get_hash(), Err(TLSHError::Length { .. })));
+    }
+
+    #[test]
+    fn test_single_input() {
+        // The merged counts of a single input are its own counts, the scaling keeps the q-ratios
+        let mut rng = SplitMix64::new(13);
+        for _ in 0..3000 {
+            let len = 50 + rng.below(3000);
+            let alphabet = 2 + rng.below(255);
+            let data: Vec<u8> = (0..len).map(|_| rng.below(alphabet) as u8).collect();
+            let mut single = TLSHAggregator::default();
+            single.add(&data);
+            let mut builder = TLSHBuilder::new();
+            builder.update(&data);
+            builder.finalize();
+            match (single.get_hash(), builder.get_hash()) {
+                (Ok(a), Ok(b)) => assert_eq!(a.to_digest(), b.to_digest()),
+                (a, b) => assert_eq!(a.is_ok(), b.is_ok(), "{a:?} {b:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_directory() {
+        let dir = std::env::temp_dir().join(format!("tlsh-aggregate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut rng = SplitMix64::new(12);
+        let files = [rng.bytes(3000), rng.bytes(900)];
+        fs::write(dir.join("a.bin"), &files[0]).unwrap();
+        fs::write(dir.join("sub/b.bin"), &files[1]).unwrap();
+
+        let options = AggregateOptions { normalize: true, weighting: Weighting::LogLength };
+        let hash = hash_directory(&dir, options).unwrap().unwrap();
+        let mut aggregator = TLSHAggregator::new(options);
+        aggregator.add(&files[1]);
+        aggregator.add(&files[0]);
+        assert_eq!(hash.to_digest(), aggregator.get_hash().unwrap().to_digest());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(hash_directory(&dir, options).is_err());
+    }
+}
@@ -7,6 +7,11 @@ struct Args {
     pub compare: Option<String>,*/
     #[arg(short('f'))]
     pub file: Option<PathBuf>,
+
+    /// Hash the segments of the file between the given comma-separated byte offsets, also
+    /// accepted as `-split` like the reference binary
+    #[arg(long("split"), value_delimiter(','), requires("file"))]
+    pub split: Option<Vec<u64>>,
     /*  #[arg(short('d'))]
         pub digest: Option<String>,
 
@@ -19,11 +24,13 @@ struct Args {
 }
 
 fn main() {
-    let args = Args::parse();
+    // the reference binary takes its long options with a single dash
+    let args = Args::parse_from(std::env::args_os().map(|arg| if arg == "-split" { "--split".into() } else { arg }));
 
     // determine mode
-    match args.file {
-        Some(file) => hash_file(file),
+    match (args.file, args.split) {
+        (Some(file), Some(split)) => hash_file_split(file, &split),
+        (Some(file), None) => hash_file(file),
         _ => {
             Args::command().print_help().unwrap();
         }
@@ -31,13 +38,32 @@ fn main() {
 }
 
 fn hash_file(file: impl AsRef<Path>) {
-    let file_bytes = std::fs::read(file.as_ref()).unwrap();
-    let mut builder = simbiota_tlsh::TLSHBuilder::new();
-    builder.update(&file_bytes);
-    builder.finalize();
+    let hash = simbiota_tlsh::hash_file(file.as_ref()).unwrap();
     println!(
         "{}\t{}",
-        builder.get_hash().unwrap().to_digest(),
+        hash.unwrap().to_digest(),
         file.as_ref().display()
     );
 }
+
+fn hash_file_split(file: impl AsRef<Path>, split: &[u64]) {
+    let mut split = split.to_vec();
+    split.sort_unstable();
+    split.dedup();
+    for segment in simbiota_tlsh::hash_file_split(file.as_ref(), &split).unwrap() {
+        let digest = match segment.hash {
+            Ok(hash) => hash.to_digest(),
+            Err(e) => {
+                eprintln!("{}: {e}", segment.offset);
+                "TNULL".to_string()
+            }
+        };
+        println!(
+            "{}\t{}\t{}-{}",
+            digest,
+            file.as_ref().display(),
+            segment.offset,
+            segment.offset + segment.len as u64
+        );
+    }
+}
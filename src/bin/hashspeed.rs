@@ -0,0 +1,37 @@
+use simbiota_tlsh::ColoredTLSHBuilder;
+use std::time::Instant;
+
+/// The best of this many runs is reported
+const RUNS: usize = 3;
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let size_mb: usize = args.get(1).map(|v| v.parse().unwrap()).unwrap_or(64);
+    let chunk_size: usize = args.get(2).map(|v| v.parse().unwrap()).unwrap_or(1 << 20);
+
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let data: Vec<u8> = (0..size_mb << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+
+    println!("hashing {} MiB in {} byte chunks", size_mb, chunk_size);
+    for color_count in [1, 2, 4, 8, 16] {
+        let colors: Vec<u8> = (0..color_count).collect();
+        let mut secs = f64::MAX;
+        for _ in 0..RUNS {
+            let mut builder = ColoredTLSHBuilder::new(&colors);
+            let start = Instant::now();
+            for chunk in data.chunks(chunk_size) {
+                builder.update(chunk);
+            }
+            builder.finalize();
+            secs = secs.min(start.elapsed().as_secs_f64());
+        }
+        println!("{:2} colors: {:8.2} MiB/s", color_count, size_mb as f64 / secs);
+    }
+}
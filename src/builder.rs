@@ -1,23 +1,152 @@
-use crate::{ColoredTLSH, hash::{EFF_BUCKETS, TLSH}, util::{calc_lvalue, Pearson}};
+use crate::{ColoredTLSH, hash::{EFF_BUCKETS, TLSH}, extended::ExtendedTLSH, keyed::{assert_distinct_markers, KeyedColor}, report::TLSHReport, util::{lvalue_of, Pearson, MAX_DATA_LENGTH, MIN_CONSERVATIVE_DATA_LENGTH, MIN_DATA_LENGTH}};
+use std::fmt;
 
 const WINDOW_SIZE: usize = 5;
 const WINDOW_SIZE_M1: usize = WINDOW_SIZE - 1;
+const PARALLEL_MIN_CHUNK: usize = 1 << 16;
+const UPDATE_BUFFER_SIZE: usize = 1 << 12;
+const CHECKPOINT_MAGIC: &[u8; 4] = b"TLSC";
+const CHECKPOINT_VERSION: u8 = 1;
+/// Magic, version, data length, number of colors, sliding window and byte counts
+const CHECKPOINT_HEADER_SIZE: usize = 4 + 1 + 8 + 4 + WINDOW_SIZE + 256 * 8;
+/// Color, checksum, key id of a keyed color or 0, and bucket counts
+const CHECKPOINT_COLOR_SIZE: usize = 1 + 1 + 4 + 256 * 8;
 
 /// An error during TLSH calculation
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum TLSHError {
-    /// The data was too short or too long for TLSH calculation
-    Length,
+    /// The data was too short for TLSH calculation
+    Length {
+        /// Length of the data
+        data_len: u64,
+        /// Shortest accepted length, see `TLSHOptions::min_length`
+        min_length: u64,
+    },
     /// The data did not have sufficient variety for TLSH calculation
-    Variety,
+    Variety {
+        /// Length of the data
+        data_len: u64,
+        /// Number of the 128 buckets with a non-zero count
+        nonzero: usize,
+        /// The quartiles of the bucket counts
+        quartiles: [u64; 3],
+    },
+    /// The data was longer than the largest length TLSH can represent (about 3.9 GiB)
+    TooLong {
+        /// Length of the data
+        data_len: u64,
+    },
 }
 
+impl fmt::Display for TLSHError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TLSHError::Length { data_len, min_length } => {
+                write!(f, "data too short for TLSH: {data_len} bytes, at least {min_length} required")
+            }
+            TLSHError::Variety { data_len, nonzero, quartiles: [q1, q2, q3] } => write!(
+                f,
+                "data of {data_len} bytes has too little variety for TLSH: {nonzero} of {EFF_BUCKETS} buckets used, quartiles {q1}/{q2}/{q3}"
+            ),
+            TLSHError::TooLong { data_len } => {
+                write!(f, "data too long for TLSH: {data_len} bytes, at most {MAX_DATA_LENGTH} allowed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TLSHError {}
+
+/// An error during restoring a builder from a checkpoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointError {
+    /// The blob is not a builder checkpoint
+    InvalidMagic,
+    /// The checkpoint was created by an unsupported format version
+    UnsupportedVersion,
+    /// The blob is truncated or has trailing data
+    InvalidLength,
+    /// The checkpoint contains a color the builder does not support
+    InvalidColor,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::InvalidMagic => write!(f, "not a TLSH builder checkpoint"),
+            CheckpointError::UnsupportedVersion => write!(f, "unsupported checkpoint format version"),
+            CheckpointError::InvalidLength => write!(f, "checkpoint truncated or followed by trailing data"),
+            CheckpointError::InvalidColor => write!(f, "checkpoint contains an unsupported color"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// Hashes of a prefix of the data, calculated during `update` at an interim point
+///
+/// See `ColoredTLSHBuilder::set_interim_points`
+#[derive(Clone, Debug)]
+pub struct InterimHash {
+    /// Length of the prefix
+    pub data_len: u64,
+    /// The hashes of the colors of the builder, in order
+    pub hashes: Vec<Result<ColoredTLSH, TLSHError>>,
+}
+
+/// Options of the hash calculation
+///
+/// The default options give the standard TLSH hashes. `conservative` corresponds to the
+/// conservative mode of the reference implementation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TLSHOptions {
+    /// Shortest data length to calculate hashes for
+    pub min_length: u64,
+    /// Calculate hashes of data with fewer than half of the buckets used too
+    ///
+    /// Such hashes are less reliable, they are marked by `ColoredTLSHBuilder::get_forced`. Data
+    /// with fewer than a quarter of the buckets used still fails with `TLSHError::Variety`.
+    pub force: bool,
+}
+
+impl TLSHOptions {
+    /// Options of the conservative mode, requiring at least 256 bytes of data
+    pub fn conservative() -> Self {
+        Self {
+            min_length: MIN_CONSERVATIVE_DATA_LENGTH,
+            ..Self::default()
+        }
+    }
+
+    /// The lvalue of `data_len` bytes, or the error of a length out of the accepted range
+    pub(crate) fn lvalue(&self, data_len: u64) -> Result<u8, TLSHError> {
+        if data_len < self.min_length {
+            return Err(TLSHError::Length {
+                data_len,
+                min_length: self.min_length,
+            });
+        }
+        lvalue_of(data_len).ok_or(TLSHError::TooLong { data_len })
+    }
+}
+
+impl Default for TLSHOptions {
+    fn default() -> Self {
+        Self {
+            min_length: MIN_DATA_LENGTH,
+            force: false,
+        }
+    }
+}
+
+#[derive(Clone)]
 struct BuilderColorData {
     pearson: Pearson,
-    a_bucket: [u32; 256],
+    a_bucket: [u64; 256],
     checksum: u8,
-    sliding_window: [u8; WINDOW_SIZE],
     finalized: Option<Result<ColoredTLSH, TLSHError>>,
+    forced: bool,
 }
 
 /// Calculates multiple different-color TLSH hashes of data
@@ -37,7 +166,7 @@ struct BuilderColorData {
 /// builder.update(&DATA[40..62]);
 /// builder.update(&DATA[62..]);
 /// builder.finalize();
-/// assert!(matches!(builder.get_hashes()[0], Err(TLSHError::Variety)));
+/// assert!(matches!(builder.get_hashes()[0], Err(TLSHError::Variety { .. })));
 /// ```
 ///
 /// ```
@@ -55,60 +184,62 @@ struct BuilderColorData {
 /// let data: Vec<u8> = (1..49).collect();
 /// builder.update(&data);
 /// builder.finalize();
-/// assert!(matches!(builder.get_hashes()[0], Err(TLSHError::Length)));
+/// assert!(matches!(builder.get_hashes()[0], Err(TLSHError::Length { .. })));
 /// ```
 
-macro_rules! j_n {
-    ($n:expr,$color_index:expr,$j:expr,$i:expr, $self:expr, $data:expr) => {
-        {
-        let j_n = $self.rng_index($j.wrapping_sub($n));
-        if $i > $n {
-            $self.colors[$color_index].sliding_window[j_n] = $data[$i-$n];
-        }
-        j_n
-        }
-    };
-}
-
 macro_rules! a_buckets {
-    ($self: expr, $n: expr, $p2: expr, $p3: expr, $p4:expr, $p5:expr, $p6:expr) => {
+    ($color: expr, $p2: expr, $p3: expr, $p4:expr, $p5:expr, $p6:expr) => {
         {
-        let i_1 = $self.colors[$n].pearson.fast_b_mapping(2,$p2, $p3,$p4) as usize;
-            $self.colors[$n].a_bucket[i_1] += 1;
-        let i_2 = $self.colors[$n].pearson.fast_b_mapping(3,$p2,$p3,$p5) as usize;
-            $self.colors[$n].a_bucket[i_2] += 1;
-        let i_3 = $self.colors[$n].pearson.fast_b_mapping(5,$p2,$p4,$p5) as usize;
-            $self.colors[$n].a_bucket[i_3] += 1;
-        let i_4 = $self.colors[$n].pearson.fast_b_mapping(7,$p2,$p4,$p6) as usize;
-            $self.colors[$n].a_bucket[i_4] += 1;
-        let i_5 = $self.colors[$n].pearson.fast_b_mapping(11,$p2,$p3,$p6) as usize;
-            $self.colors[$n].a_bucket[i_5] += 1;
-        let i_6 = $self.colors[$n].pearson.fast_b_mapping(13,$p2,$p5,$p6) as usize;
-            $self.colors[$n].a_bucket[i_6] += 1;
-        }
-    };
-    ($self: expr, $n: expr, $p2: expr, $p3: expr, $p4:expr, $p5:expr, $p6:expr, 0) => {
-        {
-        let i_1 = $self.colors[$n].pearson.p0_fast_b_mapping(49,$p2, $p3,$p4) as usize;
-            $self.colors[$n].a_bucket[i_1] += 1;
-        let i_2 = $self.colors[$n].pearson.p0_fast_b_mapping(12,$p2,$p3,$p5) as usize;
-            $self.colors[$n].a_bucket[i_2] += 1;
-        let i_3 = $self.colors[$n].pearson.p0_fast_b_mapping(178,$p2,$p4,$p5) as usize;
-            $self.colors[$n].a_bucket[i_3] += 1;
-        let i_4 = $self.colors[$n].pearson.p0_fast_b_mapping(166,$p2,$p4,$p6) as usize;
-            $self.colors[$n].a_bucket[i_4] += 1;
-        let i_5 = $self.colors[$n].pearson.p0_fast_b_mapping(84,$p2,$p3,$p6) as usize;
-            $self.colors[$n].a_bucket[i_5] += 1;
-        let i_6 = $self.colors[$n].pearson.p0_fast_b_mapping(230,$p2,$p5,$p6) as usize;
-            $self.colors[$n].a_bucket[i_6] += 1;
+        let i_1 = $color.pearson.salted_b_mapping(0,$p2,$p3,$p4) as usize;
+            $color.a_bucket[i_1] += 1;
+        let i_2 = $color.pearson.salted_b_mapping(1,$p2,$p3,$p5) as usize;
+            $color.a_bucket[i_2] += 1;
+        let i_3 = $color.pearson.salted_b_mapping(2,$p2,$p4,$p5) as usize;
+            $color.a_bucket[i_3] += 1;
+        let i_4 = $color.pearson.salted_b_mapping(3,$p2,$p4,$p6) as usize;
+            $color.a_bucket[i_4] += 1;
+        let i_5 = $color.pearson.salted_b_mapping(4,$p2,$p3,$p6) as usize;
+            $color.a_bucket[i_5] += 1;
+        let i_6 = $color.pearson.salted_b_mapping(5,$p2,$p5,$p6) as usize;
+            $color.a_bucket[i_6] += 1;
         }
     };
 }
 
+impl BuilderColorData {
+    /// Process the window `b0`..`b4`, `b0` being its last byte
+    #[inline(always)]
+    fn add_window(&mut self, b0: u8, b1: u8, b2: u8, b3: u8, b4: u8) {
+        self.checksum = self.pearson.p0_fast_b_mapping(1, b0, b1, self.checksum);
+        a_buckets!(self, b0, b1, b2, b3, b4);
+    }
+
+    /// Process the 5 windows ending with `a[4]`..`a[8]`
+    #[inline(always)]
+    fn add_windows(&mut self, a: &[u8; 9]) {
+        self.add_window(a[4], a[3], a[2], a[1], a[0]);
+        self.add_window(a[5], a[4], a[3], a[2], a[1]);
+        self.add_window(a[6], a[5], a[4], a[3], a[2]);
+        self.add_window(a[7], a[6], a[5], a[4], a[3]);
+        self.add_window(a[8], a[7], a[6], a[5], a[4]);
+    }
+}
 
+#[derive(Clone)]
 pub struct ColoredTLSHBuilder {
     colors: Vec<BuilderColorData>,
-    data_len: usize,
+    sliding_window: [u8; WINDOW_SIZE],
+    data_len: u64,
+    /// Data of small updates not processed yet, so that they are processed in larger slices
+    buffer: Vec<u8>,
+    options: TLSHOptions,
+    /// Lengths at which to calculate interim hashes, in increasing order
+    interim_points: Vec<u64>,
+    /// Index of the next interim point not reached yet
+    next_interim: usize,
+    interim_hashes: Vec<InterimHash>,
+    /// Number of each byte value in the data
+    byte_counts: [u64; 256],
 }
 
 impl ColoredTLSHBuilder {
@@ -119,21 +250,67 @@ impl ColoredTLSHBuilder {
     ///
     /// * `colors` - Slice containing the color numbers of hashes to calculate
     pub fn new(colors: &[u8]) -> Self {
+        Self::with_options(colors, TLSHOptions::default())
+    }
+
+    /// Create an instance calculating hashes with non-default options
+    ///
+    /// # Arguments
+    ///
+    /// * `colors` - Slice containing the color numbers of hashes to calculate
+    /// * `options` - Options of the hash calculation
+    pub fn with_options(colors: &[u8], options: TLSHOptions) -> Self {
+        Self::with_pearsons(colors.iter().map(|v| Pearson::new(*v)).collect(), options)
+    }
+
+    /// Create an instance calculating hashes of keyed colors
+    ///
+    /// The hashes have the markers of the colors as color numbers. See `KeyedColor`.
+    ///
+    /// # Arguments
+    ///
+    /// * `colors` - Slice containing the keyed colors of hashes to calculate
+    /// * `options` - Options of the hash calculation
+    ///
+    /// # Panics
+    ///
+    /// The method panics if two of `colors` have the same marker.
+    pub fn with_keyed_colors(colors: &[KeyedColor], options: TLSHOptions) -> Self {
+        assert_distinct_markers(colors);
+        Self::with_pearsons(colors.iter().map(|v| v.pearson.clone()).collect(), options)
+    }
+
+    fn with_pearsons(pearsons: Vec<Pearson>, options: TLSHOptions) -> Self {
         Self {
-            colors: colors
-                .iter()
-                .map(|v| BuilderColorData {
-                    pearson: Pearson::new(*v),
+            colors: pearsons
+                .into_iter()
+                .map(|pearson| BuilderColorData {
+                    pearson,
                     a_bucket: [0; 256],
                     checksum: 0,
                     finalized: None,
-                    sliding_window: [0; WINDOW_SIZE],
+                    forced: false,
                 })
                 .collect(),
+            sliding_window: [0; WINDOW_SIZE],
             data_len: 0,
+            buffer: Vec::new(),
+            options,
+            interim_points: Vec::new(),
+            next_interim: 0,
+            interim_hashes: Vec::new(),
+            byte_counts: [0; 256],
         }
     }
 
+    /// Change the options of the hash calculation
+    ///
+    /// The options are only used by `finalize`, so they can be changed at any time, e.g. after
+    /// restoring a checkpoint, which does not store them.
+    pub fn set_options(&mut self, options: TLSHOptions) {
+        self.options = options;
+    }
+
     #[allow(dead_code)]
     /// Clear the builder to reuse it to calculate the same hash colors of other data
     pub fn reset(&mut self) {
@@ -143,6 +320,59 @@ impl ColoredTLSHBuilder {
             v.finalized = None;
         }
         self.data_len = 0;
+        self.buffer.clear();
+        self.next_interim = 0;
+        self.interim_hashes.clear();
+        self.byte_counts = [0; 256];
+    }
+
+    fn count_bytes(&mut self, data: &[u8]) {
+        for b in data {
+            self.byte_counts[*b as usize] += 1;
+        }
+    }
+
+    /// Calculate interim hashes whenever the length of the processed data reaches one of `points`
+    ///
+    /// The hashes are those `finalize` would give at the points, collect them by
+    /// `take_interim_hashes`. Points already passed are ignored, the points are kept by `reset`
+    /// but not by a checkpoint.
+    pub fn set_interim_points(&mut self, points: &[u64]) {
+        let mut points = points.to_vec();
+        points.sort_unstable();
+        points.dedup();
+        let fed_len = self.fed_len();
+        self.next_interim = points.partition_point(|point| *point <= fed_len);
+        self.interim_points = points;
+    }
+
+    /// Remove and return the interim hashes calculated so far, in the order of their points
+    pub fn take_interim_hashes(&mut self) -> Vec<InterimHash> {
+        std::mem::take(&mut self.interim_hashes)
+    }
+
+    /// Length of the data added, including the buffered data
+    fn fed_len(&self) -> u64 {
+        self.data_len + self.buffer.len() as u64
+    }
+
+    /// Pass `data` to `update` in parts ending at the interim points, calculating the interim
+    /// hashes in between
+    fn split_at_interim_points(&mut self, mut data: &[u8], mut update: impl FnMut(&mut Self, &[u8])) {
+        while let Some(&point) = self.interim_points.get(self.next_interim) {
+            let take = point - self.fed_len();
+            if take > data.len() as u64 {
+                break;
+            }
+            let (head, rest) = data.split_at(take as usize);
+            update(self, head);
+            data = rest;
+            self.next_interim += 1;
+            self.flush_buffer();
+            let hashes = self.current_hashes(self.data_len).into_iter().map(|result| result.map(|(hash, _)| hash)).collect();
+            self.interim_hashes.push(InterimHash { data_len: point, hashes });
+        }
+        update(self, data);
     }
 
 
@@ -150,278 +380,299 @@ impl ColoredTLSHBuilder {
         (index.wrapping_add(WINDOW_SIZE)) % WINDOW_SIZE
     }
 
+    /// Process `data` in a single pass, reading each window once for all colors
     fn fast_update(&mut self, data: &[u8]) {
         assert_eq!(WINDOW_SIZE, 5);
         let len = data.len();
-        for n in 0..self.colors.len() {
-            let color = self.colors[n].pearson.color;
-            let bucket_fn = if color == 0 {
-                |slf: &mut Self, n: usize, p2: u8, p3: u8, p4: u8, p5: u8, p6: u8| {
-                    a_buckets!(slf, n, p2, p3, p4, p5, p6, 0);
-                }
-            } else {
-                |slf: &mut Self, n: usize, p2: u8, p3: u8, p4: u8, p5: u8, p6: u8| {
-                    a_buckets!(slf, n, p2, p3, p4, p5, p6);
-                }
-            };
-            let mut j: usize = (self.data_len % (WINDOW_SIZE)) as i32 as usize;
-            let mut fed_len = self.data_len;
-            let mut checksum: u8 = self.colors[n].checksum;
-
-            let mut i: usize = 0;
-            while i < len {
-                if fed_len >= (WINDOW_SIZE_M1) {
-                    if (i >= 4) && (i + 5 < len) {
-                        let a0 = data[i - 4];
-                        let a1 = data[i - 3];
-                        let a2 = data[i - 2];
-                        let a3 = data[i - 1];
-                        let a4 = data[i];
-                        let a5 = data[i + 1];
-                        let a6 = data[i + 2];
-                        let a7 = data[i + 3];
-                        let a8 = data[i + 4];
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, a4, a3, checksum);
-                        bucket_fn(self,n,a4,a3,a2,a1,a0);
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, a5, a4, checksum);
-                        bucket_fn(self,n, a5,a4,a3,a2,a1);
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, a6, a5, checksum);
-                        bucket_fn(self,n, a6,a5,a4,a3,a2);
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, a7, a6, checksum);
-                        bucket_fn(self,n, a7,a6,a5,a4,a3);
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, a8, a7, checksum);
-                        bucket_fn(self,n, a8, a7,a6,a5,a4);
-
-                        i += 5;
-                        fed_len += 5;
-                        j=self.rng_index(j+5);
-                    } else {
-                        self.colors[n].sliding_window[j] = data[i];
-                        let j_1 = j_n!(1,n,j,i,self,data);
-                        let j_2 = j_n!(2,n,j,i,self,data);
-                        let j_3 = j_n!(3,n,j,i,self,data);
-                        let j_4 = j_n!(4,n,j,i,self,data);
-
-                        checksum = self.colors[n].pearson.p0_fast_b_mapping(1, self.colors[n].sliding_window[j], self.colors[n].sliding_window[j_1], checksum);
-                        a_buckets!(self,n, self.colors[n].sliding_window[j], self.colors[n].sliding_window[j_1], self.colors[n].sliding_window[j_2], self.colors[n].sliding_window[j_3], self.colors[n].sliding_window[j_4]);
-
-                        i += 1;
-                        fed_len += 1;
-                        j = self.rng_index(j + 1);
+        let mut j = (self.data_len % WINDOW_SIZE as u64) as usize;
+        let mut fed_len = self.data_len;
+
+        let mut i: usize = 0;
+        while i < len {
+            if fed_len >= WINDOW_SIZE_M1 as u64 {
+                if (i >= 4) && (i + 5 < len) {
+                    let a: &[u8; 9] = data[i - 4..i + 5].try_into().unwrap();
+                    for color in self.colors.iter_mut() {
+                        color.add_windows(a);
                     }
+
+                    i += 5;
+                    fed_len += 5;
                 } else {
+                    self.sliding_window[j] = data[i];
+                    for n in 1..WINDOW_SIZE {
+                        if i >= n {
+                            self.sliding_window[self.rng_index(j.wrapping_sub(n))] = data[i - n];
+                        }
+                    }
+                    let w = self.sliding_window;
+                    let (w0, w1, w2, w3, w4) = (
+                        w[j],
+                        w[self.rng_index(j.wrapping_sub(1))],
+                        w[self.rng_index(j.wrapping_sub(2))],
+                        w[self.rng_index(j.wrapping_sub(3))],
+                        w[self.rng_index(j.wrapping_sub(4))],
+                    );
+                    for color in self.colors.iter_mut() {
+                        color.add_window(w0, w1, w2, w3, w4);
+                    }
+
                     i += 1;
                     fed_len += 1;
                     j = self.rng_index(j + 1);
                 }
+            } else {
+                self.sliding_window[j] = data[i];
+                i += 1;
+                fed_len += 1;
+                j = self.rng_index(j + 1);
             }
-            self.colors[n].checksum = checksum;
-
         }
-        self.data_len += len;
-
+        self.data_len += len as u64;
     }
 
 
     /// Add the next segment of data to process
     ///
-    /// # Panics
-    ///
-    /// The method panics if adding less than 4 bytes on its first call (or first call after `clear`).
+    /// Segments of any size can be added. Small segments are collected in an internal 4 KiB
+    /// buffer and processed together, so the throughput does not depend on the segment size.
     pub fn update(&mut self, data: &[u8]) {
+        self.split_at_interim_points(data, Self::update_buffered);
+    }
+
+    fn update_buffered(&mut self, data: &[u8]) {
         for v in self.colors.iter_mut() {
             v.finalized = None;
         }
-        self.fast_update(data);
+        self.count_bytes(data);
+        if self.buffer.is_empty() && data.len() >= UPDATE_BUFFER_SIZE {
+            self.fast_update(data);
+            return;
+        }
+        let take = (UPDATE_BUFFER_SIZE - self.buffer.len()).min(data.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        if self.buffer.len() < UPDATE_BUFFER_SIZE {
+            return;
+        }
+        self.flush_buffer();
+        let rest = &data[take..];
+        if rest.len() >= UPDATE_BUFFER_SIZE {
+            self.fast_update(rest);
+        } else {
+            self.buffer.extend_from_slice(rest);
+        }
     }
 
-    pub fn fast_finalize(&mut self) {
-        let lvalue = match calc_lvalue(self.data_len as u32) {
-            Some(lv) => lv,
-            None => {
-                for v in self.colors.iter_mut() {
-                    v.finalized = Some(Err(TLSHError::Length));
-                }
-                return;
-            }
-        };
+    /// Process the buffered data of previous `update` calls
+    fn flush_buffer(&mut self) {
+        if !self.buffer.is_empty() {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            self.fast_update(&buffer);
+            buffer.clear();
+            self.buffer = buffer;
+        }
+    }
 
-        for n in 0..self.colors.len() {
-            let (q1, q2, q3) = self.find_quartile(&self.colors[n].a_bucket);
-            if q3 == 0 {
-                self.colors[n].finalized = Some(Err(TLSHError::Variety));
-                return;
-            }
+    /// Process the buffered data and return the bucket counts and checksum of the `n`th color,
+    /// with the length of the data
+    pub(crate) fn color_state(&mut self, n: usize) -> (&[u64; 256], u8, u64) {
+        self.flush_buffer();
+        let v = &self.colors[n];
+        (&v.a_bucket, v.checksum, self.data_len)
+    }
 
-            let mut nonzero = 0;
-            for i in 0..32 {
-                for j in 0..4 {
-                    if self.colors[n].a_bucket[4 * i + j] > 0 {
-                        nonzero += 1;
+    /// Add the next segment of data to process, using `threads` threads
+    ///
+    /// The result is identical to calling `update` with the same data. Data shorter than
+    /// 64 KiB per thread is processed by `update` on the calling thread.
+    pub fn update_parallel(&mut self, data: &[u8], threads: usize) {
+        self.split_at_interim_points(data, |builder, data| builder.update_parallel_split(data, threads));
+    }
+
+    fn update_parallel_split(&mut self, data: &[u8], threads: usize) {
+        let chunk_len = data.len().div_ceil(threads.max(1));
+        if threads <= 1 || chunk_len < PARALLEL_MIN_CHUNK {
+            self.update_buffered(data);
+            return;
+        }
+        for v in self.colors.iter_mut() {
+            v.finalized = None;
+        }
+        self.flush_buffer();
+
+        let pearsons: Vec<Pearson> = self.colors.iter().map(|v| v.pearson.clone()).collect();
+        let pearsons = &pearsons;
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (chunk_len..data.len())
+                .step_by(chunk_len)
+                .map(|start| {
+                    let end = (start + chunk_len).min(data.len());
+                    scope.spawn(move || {
+                        let mut part = ColoredTLSHBuilder::with_pearsons(pearsons.clone(), TLSHOptions::default());
+                        part.fast_update(&data[start - WINDOW_SIZE_M1..end]);
+                        part.count_bytes(&data[start..end]);
+                        part
+                    })
+                })
+                .collect();
+
+            self.fast_update(&data[..chunk_len]);
+            self.count_bytes(&data[..chunk_len]);
+            self.update_checksums(&data[chunk_len..]);
+            for worker in workers {
+                let part = worker.join().expect("Hashing thread panicked");
+                for (v, p) in self.colors.iter_mut().zip(&part.colors) {
+                    for (a, b) in v.a_bucket.iter_mut().zip(&p.a_bucket) {
+                        *a += b;
                     }
                 }
+                for (a, b) in self.byte_counts.iter_mut().zip(&part.byte_counts) {
+                    *a += b;
+                }
             }
+        });
+    }
 
-            if nonzero <= 4 * 32 / 2 {
-                self.colors[n].finalized = Some(Err(TLSHError::Variety));
-                return;
-            }
-            let mut colored_tlsh = ColoredTLSH {
-                color: 0,
-                tlsh: TLSH {
-                    checksum: 0,
-                    lvalue: 0,
-                    q_ratios: 0,
-                    codes: [0; 32],
-                }
-            };
-            for i in 0..32 {
-                let mut h: u8 = 0;
-                for j in 0..4 {
-                    let k = self.colors[n].a_bucket[4 * i + j];
-                    if q3 < k {
-                        h += 3 << (j * 2);
-                    }else if q2 < k{
-                        h += 2 << (j * 2);
-                    } else if q1 < k {
-                        h += 1 << (j * 2);
-                    }
+    /// Advances the checksums and the sliding windows over `data` without counting buckets
+    fn update_checksums(&mut self, data: &[u8]) {
+        let data_len = self.data_len;
+        for v in self.colors.iter_mut() {
+            let mut prev = self.sliding_window[((data_len + WINDOW_SIZE_M1 as u64) % WINDOW_SIZE as u64) as usize];
+            let mut checksum = v.checksum;
+            for (i, b) in data.iter().enumerate() {
+                if data_len + i as u64 >= WINDOW_SIZE_M1 as u64 {
+                    checksum = v.pearson.p0_fast_b_mapping(1, *b, prev, checksum);
                 }
-                colored_tlsh.tlsh.codes[i] = h;
+                prev = *b;
             }
-
-            colored_tlsh.tlsh.lvalue = lvalue;
-            let q1r = (((q1 * 100) as f32) / (q3 as f32) % 16.0) as u8;
-            let q2r = (((q2 * 100) as f32) / (q3 as f32) % 16.0) as u8;
-            colored_tlsh.tlsh.q_ratios = (q2r << 4) | q1r;
-            colored_tlsh.tlsh.checksum = self.colors[n].checksum;
-            colored_tlsh.color =self.colors[n].pearson.color;
-
-            self.colors[n].finalized = Some(Ok(colored_tlsh));
+            v.checksum = checksum;
         }
+        for (i, b) in data.iter().enumerate().skip(data.len().saturating_sub(WINDOW_SIZE)) {
+            self.sliding_window[((data_len + i as u64) % WINDOW_SIZE as u64) as usize] = *b;
+        }
+        self.data_len += data.len() as u64;
     }
 
-    fn find_quartile(&self, bucket: &[u32; 256]) -> (u32, u32, u32) {
-        let mut bucket_copy:[u32; EFF_BUCKETS] = [0; EFF_BUCKETS];
-        let mut short_cut_left:[u32; EFF_BUCKETS] = [0; EFF_BUCKETS];
-        let mut short_cut_right:[u32; EFF_BUCKETS] = [0; EFF_BUCKETS];
-        let mut spl = 0;
-        let mut spr = 0;
-        let p1 = EFF_BUCKETS / 4 - 1;
-        let p2 = EFF_BUCKETS / 2 - 1;
-        let p3 = EFF_BUCKETS - EFF_BUCKETS/4 - 1;
-        let end = EFF_BUCKETS - 1;
-        let mut q1: u32 = 0;
-        let q2: u32;
-        let mut q3: u32 = 0;
-
-        bucket_copy[..=end].copy_from_slice(&bucket[..=end]);
-
-        let mut l = 0;
-        let mut r = end;
-        loop {
-            let ret = self.partition(&mut bucket_copy, l, r);
-            if ret > p2 {
-                r = ret - 1;
-                short_cut_right[spr] = ret as u32;
-                spr += 1;
-            } else if ret < p2 {
-                l = ret + 1;
-                short_cut_left[spl] = ret as u32;
-                spl += 1;
-            } else {
-                q2 = bucket_copy[p2];
-                break;
-            }
-        }
+    /// Calculate the hashes of `data` as a whole, discarding the previous state
+    ///
+    /// Reuses the Pearson tables of the builder.
+    pub(crate) fn hash_complete(&mut self, data: &[u8]) -> Vec<Result<ColoredTLSH, TLSHError>> {
+        self.reset();
+        self.count_bytes(data);
+        self.fast_update(data);
+        self.fast_finalize();
+        self.get_hashes()
+    }
 
-        short_cut_left[spl] = (p2 - 1) as u32;
-        short_cut_right[spr] = (p2 + 1) as u32;
-
-        let mut l = 0;
-        for i in 0..(spl+1) {
-            let mut r = short_cut_left[i] as usize;
-            if r > p1 {
-                loop {
-                    let ret = self.partition( &mut bucket_copy, l, r);
-                    if ret > p1 {
-                        r = ret-1;
-                    } else if ret < p1 {
-                        l = ret+1;
-                    } else {
-                        q1 = bucket_copy[p1];
-                        break;
-                    }
-                }
-                break;
-            } else if r < p1  {
-                l = r;
-            } else {
-                q1 = bucket_copy[p1];
-                break;
-            }
-        }
+    /// Calculate the hash of each color, independently of the others
+    pub fn fast_finalize(&mut self) {
+        self.finalize_with_length(self.fed_len());
+    }
 
-        let mut r = end;
-        for i in 0..(spr+1) {
-            let mut l = short_cut_right[i] as usize;
-            if l < p3  {
-                loop {
-                    let ret = self.partition( &mut bucket_copy, l, r );
-                    if ret > p3 {
-                        r = ret-1;
-                    } else if ret < p3  {
-                        l = ret+1;
-                    } else {
-                        q3 = bucket_copy[p3];
-                        break;
-                    }
-                }
-                break;
-            } else if l > p3  {
-                r = l;
-            } else {
-                q3 = bucket_copy[p3];
-                break;
-            }
+    /// Calculate the hash of each color as if the processed data was `data_len` bytes long
+    ///
+    /// The lvalue and the length checks use `data_len`, e.g. the length of the data of which
+    /// samples were processed.
+    pub(crate) fn finalize_with_length(&mut self, data_len: u64) {
+        self.flush_buffer();
+        let results = self.current_hashes(data_len);
+        for (v, result) in self.colors.iter_mut().zip(results) {
+            v.forced = matches!(result, Ok((_, true)));
+            v.finalized = Some(result.map(|(hash, _)| hash));
         }
+    }
 
-        (q1, q2, q3)
+    /// Calculate the hash of each color from the processed data, with whether it was forced
+    fn current_hashes(&self, data_len: u64) -> Vec<Result<(ColoredTLSH, bool), TLSHError>> {
+        let lvalue = self.options.lvalue(data_len);
+        let force = self.options.force;
+        self.colors
+            .iter()
+            .map(|v| {
+                lvalue
+                    .and_then(|lvalue| {
+                        Self::finalize_buckets(&v.a_bucket, v.checksum, lvalue, v.pearson.color, data_len, force)
+                    })
+                    .map(|(hash, forced)| (ColoredTLSH { key_id: v.pearson.key_id, ..hash }, forced))
+            })
+            .collect()
     }
-    fn partition(&self, buf: &mut [u32; EFF_BUCKETS], left: usize, right: usize) -> usize {
-        if left == right {
-            return left;
+
+    /// Calculate a hash from the bucket counts, checksum and lvalue of a color
+    ///
+    /// With `force`, data of insufficient variety still gets a hash, returned with a `true` flag.
+    pub(crate) fn finalize_buckets(
+        a_bucket: &[u64; 256],
+        checksum: u8,
+        lvalue: u8,
+        color: u8,
+        data_len: u64,
+        force: bool,
+    ) -> Result<(ColoredTLSH, bool), TLSHError> {
+        let (q1, q2, q3) = Self::find_quartile(a_bucket);
+        let nonzero = a_bucket[..EFF_BUCKETS].iter().filter(|count| **count > 0).count();
+        let variety_error = TLSHError::Variety {
+            data_len,
+            nonzero,
+            quartiles: [q1, q2, q3],
+        };
+        if q3 == 0 {
+            return Err(variety_error);
         }
 
-        if left + 1 == right {
-            if buf[left] > buf[right] {
-                buf.swap(left, right);
+        let forced = nonzero <= EFF_BUCKETS / 2;
+        if forced && !force {
+            return Err(variety_error);
+        }
+        let mut colored_tlsh = ColoredTLSH {
+            color: 0,
+            key_id: None,
+            tlsh: TLSH {
+                checksum: 0,
+                lvalue: 0,
+                q_ratios: 0,
+                codes: [0; 32],
+            }
+        };
+        for i in 0..32 {
+            let mut h: u8 = 0;
+            for j in 0..4 {
+                let k = a_bucket[4 * i + j];
+                if q3 < k {
+                    h += 3 << (j * 2);
+                }else if q2 < k{
+                    h += 2 << (j * 2);
+                } else if q1 < k {
+                    h += 1 << (j * 2);
+                }
             }
-            return left;
+            colored_tlsh.tlsh.codes[i] = h;
         }
 
-        let mut ret = left;
-        let pivot = (left + right) >> 1;
+        colored_tlsh.tlsh.lvalue = lvalue;
+        let q1r = (((q1 * 100) as f32) / (q3 as f32) % 16.0) as u8;
+        let q2r = (((q2 * 100) as f32) / (q3 as f32) % 16.0) as u8;
+        colored_tlsh.tlsh.q_ratios = (q2r << 4) | q1r;
+        colored_tlsh.tlsh.checksum = checksum;
+        colored_tlsh.color = color;
 
-        let val = buf[pivot];
-        buf[pivot] = buf[right];
-        buf[right] = val;
+        Ok((colored_tlsh, forced))
+    }
 
-        for i in left..right {
-            if buf[i] < val {
-                buf.swap(ret, i);
-                ret += 1;
-            }
-        }
-        buf[right] = buf[ret];
-        buf[ret] = val;
+    /// The 32nd, 64th and 96th smallest of the effective bucket counts
+    ///
+    /// Short data has few distinct counts, so the selection has to cope with many duplicates.
+    pub(crate) fn find_quartile(bucket: &[u64; 256]) -> (u64, u64, u64) {
+        let p1 = EFF_BUCKETS / 4 - 1;
+        let p2 = EFF_BUCKETS / 2 - 1;
+        let p3 = EFF_BUCKETS - EFF_BUCKETS / 4 - 1;
 
-        ret
+        let mut bucket_copy: [u64; EFF_BUCKETS] = bucket[..EFF_BUCKETS].try_into().unwrap();
+        let (lower, q2, upper) = bucket_copy.select_nth_unstable(p2);
+        let q2 = *q2;
+        let q1 = *lower.select_nth_unstable(p1).1;
+        let q3 = *upper.select_nth_unstable(p3 - p2 - 1).1;
+        (q1, q2, q3)
     }
 
 
@@ -445,6 +696,170 @@ impl ColoredTLSHBuilder {
             .collect()
     }
 
+    /// Retreive the calculated hashes with the details of their calculation
+    ///
+    /// # Panics
+    ///
+    /// The method panics if called without a `finalize` call since the last `update`.
+    pub fn get_reports(&self) -> Vec<TLSHReport> {
+        self.colors
+            .iter()
+            .map(|v| {
+                let hash = v.finalized.expect("Calling get_reports before finalize");
+                TLSHReport::new(hash, v.a_bucket, v.forced, &self.byte_counts)
+            })
+            .collect()
+    }
+
+    /// Retreive the calculated hashes as extended hashes, keeping 8 bits per bucket
+    ///
+    /// # Panics
+    ///
+    /// The method panics if called without a `finalize` call since the last `update`.
+    pub fn get_extended_hashes(&self) -> Vec<Result<ExtendedTLSH, TLSHError>> {
+        self.get_reports().iter().map(ExtendedTLSH::from_report).collect()
+    }
+
+    /// Retreive whether the calculated hashes were forced from data of insufficient variety
+    ///
+    /// Only possible with the `force` option, see `TLSHOptions`.
+    ///
+    /// # Panics
+    ///
+    /// The method panics if called without a `finalize` call since the last `update`.
+    pub fn get_forced(&self) -> Vec<bool> {
+        self.colors
+            .iter()
+            .map(|v| {
+                assert!(v.finalized.is_some(), "Calling get_forced before finalize");
+                v.forced
+            })
+            .collect()
+    }
+
+    /// Export the state of the builder as a versioned byte blob
+    ///
+    /// Restore it by `from_checkpoint` to continue hashing where it was left off. The calculated
+    /// hashes are not part of the checkpoint, call `finalize` after restoring.
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        if !self.buffer.is_empty() {
+            let mut flushed = self.clone();
+            flushed.flush_buffer();
+            return flushed.to_checkpoint();
+        }
+        let mut blob = Vec::with_capacity(CHECKPOINT_HEADER_SIZE + self.colors.len() * CHECKPOINT_COLOR_SIZE);
+        blob.extend_from_slice(CHECKPOINT_MAGIC);
+        blob.push(CHECKPOINT_VERSION);
+        blob.extend_from_slice(&self.data_len.to_le_bytes());
+        blob.extend_from_slice(&(self.colors.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&self.sliding_window);
+        for count in self.byte_counts {
+            blob.extend_from_slice(&count.to_le_bytes());
+        }
+        for v in &self.colors {
+            blob.push(v.pearson.color);
+            blob.push(v.checksum);
+            blob.extend_from_slice(&v.pearson.key_id.unwrap_or(0).to_le_bytes());
+            for count in v.a_bucket {
+                blob.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        blob
+    }
+
+    /// Restore a builder from a blob created by `to_checkpoint`
+    ///
+    /// Fails with `InvalidColor` for checkpoints of builders calculating keyed colors, restore
+    /// them by `from_keyed_checkpoint`.
+    pub fn from_checkpoint(blob: &[u8]) -> Result<Self, CheckpointError> {
+        Self::restore_checkpoint(blob, |color| (color & !0xf == 0).then(|| Pearson::new(color)))
+    }
+
+    /// Restore a builder calculating keyed colors from a blob created by `to_checkpoint`
+    ///
+    /// The keys are not part of the checkpoint, only their key ids, `colors` has to contain the
+    /// keyed colors of the builder. Fails with `InvalidColor` if a key id does not match.
+    /// Standard colors are restored as by `from_checkpoint`.
+    ///
+    /// # Panics
+    ///
+    /// The method panics if two of `colors` have the same marker.
+    pub fn from_keyed_checkpoint(blob: &[u8], colors: &[KeyedColor]) -> Result<Self, CheckpointError> {
+        assert_distinct_markers(colors);
+        Self::restore_checkpoint(blob, |color| match colors.iter().find(|c| c.marker() == color) {
+            Some(keyed) => Some(keyed.pearson.clone()),
+            None => (color & !0xf == 0).then(|| Pearson::new(color)),
+        })
+    }
+
+    fn restore_checkpoint(blob: &[u8], pearson_of: impl Fn(u8) -> Option<Pearson>) -> Result<Self, CheckpointError> {
+        if blob.len() < CHECKPOINT_HEADER_SIZE {
+            return Err(CheckpointError::InvalidLength);
+        }
+        if &blob[..4] != CHECKPOINT_MAGIC {
+            return Err(CheckpointError::InvalidMagic);
+        }
+        if blob[4] != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion);
+        }
+        let color_count = u32::from_le_bytes(blob[13..17].try_into().unwrap()) as usize;
+        let colors = &blob[CHECKPOINT_HEADER_SIZE..];
+        if color_count.checked_mul(CHECKPOINT_COLOR_SIZE) != Some(colors.len()) {
+            return Err(CheckpointError::InvalidLength);
+        }
+
+        let mut builder = Self::new(&[]);
+        builder.data_len = u64::from_le_bytes(blob[5..13].try_into().unwrap());
+        builder.sliding_window.copy_from_slice(&blob[17..17 + WINDOW_SIZE]);
+        for (count, bytes) in builder.byte_counts.iter_mut().zip(blob[17 + WINDOW_SIZE..].chunks_exact(8)) {
+            *count = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        for color in colors.chunks_exact(CHECKPOINT_COLOR_SIZE) {
+            let key_id = u32::from_le_bytes(color[2..6].try_into().unwrap());
+            let pearson = pearson_of(color[0])
+                .filter(|pearson| pearson.key_id.unwrap_or(0) == key_id)
+                .ok_or(CheckpointError::InvalidColor)?;
+            let mut a_bucket = [0; 256];
+            for (count, bytes) in a_bucket.iter_mut().zip(color[6..].chunks_exact(8)) {
+                *count = u64::from_le_bytes(bytes.try_into().unwrap());
+            }
+            builder.colors.push(BuilderColorData {
+                pearson,
+                a_bucket,
+                checksum: color[1],
+                finalized: None,
+                forced: false,
+            });
+        }
+        Ok(builder)
+    }
+}
+
+impl TLSH {
+    /// Calculate a hash from bucket counts computed elsewhere
+    ///
+    /// Quantizes the first 128 of `buckets` as `ColoredTLSHBuilder::finalize` does, with the
+    /// default options. `checksum` and the lvalue of `data_len` are stored in the hash as they
+    /// are. The bucket counts of data are available from `ColoredTLSHBuilder::get_reports`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::simbiota_tlsh::{TLSH, TLSHBuilder};
+    /// let data = include_bytes!("../test/data/random.txt");
+    /// let mut builder = TLSHBuilder::new();
+    /// builder.update(data);
+    /// builder.finalize();
+    /// let report = builder.get_report();
+    /// let hash = report.hash.unwrap().tlsh;
+    /// let rebuilt = TLSH::from_buckets(&report.buckets, hash.checksum, data.len() as u64).unwrap();
+    /// assert_eq!(rebuilt.to_digest(), hash.to_digest());
+    /// ```
+    pub fn from_buckets(buckets: &[u64; 256], checksum: u8, data_len: u64) -> Result<TLSH, TLSHError> {
+        let lvalue = TLSHOptions::default().lvalue(data_len)?;
+        let (hash, _) = ColoredTLSHBuilder::finalize_buckets(buckets, checksum, lvalue, 0, data_len, false)?;
+        Ok(hash.tlsh)
+    }
 }
 
 impl Default for ColoredTLSHBuilder {
@@ -457,8 +872,9 @@ impl Default for ColoredTLSHBuilder {
     }
 }
 
+#[derive(Clone)]
 pub struct TLSHBuilder {
-    color_builder: ColoredTLSHBuilder
+    pub(crate) color_builder: ColoredTLSHBuilder
 }
 
 impl TLSHBuilder {
@@ -468,10 +884,31 @@ impl TLSHBuilder {
         }
     }
 
+    /// Create an instance calculating the hash with non-default options
+    pub fn with_options(options: TLSHOptions) -> Self {
+        Self {
+            color_builder: ColoredTLSHBuilder::with_options(&[0], options)
+        }
+    }
+
+    /// Change the options of the hash calculation
+    ///
+    /// See `ColoredTLSHBuilder::set_options`
+    pub fn set_options(&mut self, options: TLSHOptions) {
+        self.color_builder.set_options(options);
+    }
+
     pub fn update(&mut self, data: &[u8]) {
         self.color_builder.update(data);
     }
 
+    /// Add the next segment of data to process, using `threads` threads
+    ///
+    /// See `ColoredTLSHBuilder::update_parallel`
+    pub fn update_parallel(&mut self, data: &[u8], threads: usize) {
+        self.color_builder.update_parallel(data, threads);
+    }
+
     pub fn finalize(&mut self) {
         self.color_builder.finalize();
     }
@@ -479,6 +916,63 @@ impl TLSHBuilder {
     pub fn get_hash(&self) -> Result<TLSH, TLSHError> {
         self.color_builder.get_hashes()[0].map(|ch| ch.tlsh)
     }
+
+    /// Retreive whether the calculated hash was forced from data of insufficient variety
+    ///
+    /// See `ColoredTLSHBuilder::get_forced`
+    pub fn get_forced(&self) -> bool {
+        self.color_builder.get_forced()[0]
+    }
+
+    /// Retreive the calculated hash as an extended hash, keeping 8 bits per bucket
+    ///
+    /// See `ColoredTLSHBuilder::get_extended_hashes`
+    pub fn get_extended_hash(&self) -> Result<ExtendedTLSH, TLSHError> {
+        self.color_builder.get_extended_hashes()[0]
+    }
+
+    /// Retreive the calculated hash with the details of its calculation
+    ///
+    /// See `ColoredTLSHBuilder::get_reports`
+    pub fn get_report(&self) -> TLSHReport {
+        self.color_builder.get_reports().remove(0)
+    }
+
+    /// Calculate interim hashes whenever the length of the processed data reaches one of `points`
+    ///
+    /// See `ColoredTLSHBuilder::set_interim_points`
+    pub fn set_interim_points(&mut self, points: &[u64]) {
+        self.color_builder.set_interim_points(points);
+    }
+
+    /// Remove and return the interim hashes calculated so far, with the lengths of their prefixes
+    ///
+    /// See `ColoredTLSHBuilder::take_interim_hashes`
+    pub fn take_interim_hashes(&mut self) -> Vec<(u64, Result<TLSH, TLSHError>)> {
+        self.color_builder
+            .take_interim_hashes()
+            .into_iter()
+            .map(|interim| (interim.data_len, interim.hashes[0].map(|hash| hash.tlsh)))
+            .collect()
+    }
+
+    /// Export the state of the builder as a versioned byte blob
+    ///
+    /// See `ColoredTLSHBuilder::to_checkpoint`
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        self.color_builder.to_checkpoint()
+    }
+
+    /// Restore a builder from a blob created by `to_checkpoint`
+    ///
+    /// Fails with `InvalidColor` for checkpoints of builders calculating other colors than 0.
+    pub fn from_checkpoint(blob: &[u8]) -> Result<Self, CheckpointError> {
+        let color_builder = ColoredTLSHBuilder::from_checkpoint(blob)?;
+        if color_builder.colors.len() != 1 || color_builder.colors[0].pearson.color != 0 {
+            return Err(CheckpointError::InvalidColor);
+        }
+        Ok(Self { color_builder })
+    }
 }
 
 impl Default for TLSHBuilder {
@@ -490,6 +984,7 @@ impl Default for TLSHBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::calc_lvalue;
     #[test]
     fn test_random_bytes() {
         // Reference: T19411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110
@@ -516,13 +1011,327 @@ mod tests {
         assert_eq!(digest, "9411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110")
     }
     
+    #[test]
+    fn test_all_colors() {
+        let expected = [
+            "009411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110",
+            "019E11A831AB1E036E863F954553B948B2941F495C98BF29DB1F771B186A88410531FC24",
+            "026A11CC23B9E92E30F7D16F880DB41E4D341D7196F06574B14005C59160D8CE54DEDD70",
+            "039A11D86D9C20AD520F1DC6DCA9FABC840777368D719EC17E01C6B2892BD351A12F8F90",
+            "048611A8AFD14B351586EE938D1AEDFA95180722706106925EC422DBFADF405DC25530E5",
+            "05CE11A588190C7E17F29D9E7299EA40A9AB21D1D7A7D37B3A2020A10C4673B2DF8409A0",
+            "068511D828E999B43FC882ACD2804154A9B659E630C2B1B8E80692EE28F000C4467A5327",
+            "07AF1175A71DBC0220D901A80818BA78C61C253375443E26FF6873A548F065DA2990B25B",
+            "083011C8CB19BCE89CA3A52B0498B088259FC3E06272C46F97D5D2B687F5335E4F21051B",
+            "098C110C7749D051EF706DBF1F0E8640B443322851B193365F90019B91E10FBC39637183",
+            "0AC711A874C62F473665C25ECD18CB426944EDB2EA816EA91D120584337B44EF47E30776",
+            "0B4311D86C11F8062C062B86C71ED1773CA60BC8DCA5894BE23826A254B9CDE2020608AA",
+            "0CF811A894A055B3BF75D4679EC1595D1CB7DCEF7A0058CC6494F4080362EB847A6460AA",
+            "0DB011B2222D10293024CD9CA88C708F6FA8BFE2EA813028A1FD6591A8165150CE00BBB3",
+            "0E6711A56116926EBB2A8C3A5A8A7858180EDF0D9205F9BE2D2214BD2C7010F611F4F2A2",
+            "0FBA11D814305BB83685883B62361C1844B0C37B4BB8A1EBA7D7082962EA8127FD46508E",
+        ];
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let colors: Vec<u8> = (0..16).collect();
+        let mut builder = ColoredTLSHBuilder::new(&colors);
+        for chunk in random_bytes.chunks(100) {
+            builder.update(chunk);
+        }
+        builder.finalize();
+        for (hash, digest) in builder.get_hashes().iter().zip(expected) {
+            assert_eq!(hash.unwrap().to_digest(), digest);
+        }
+    }
+
+    #[test]
+    fn test_parallel() {
+        let mut rng = crate::util::SplitMix64::new(11);
+        let data: Vec<u8> = (0..1_000_003).map(|_| (rng.next_u64() % 7) as u8 * 31).collect();
+        let colors = [0, 3, 15];
+        let mut expected = ColoredTLSHBuilder::new(&colors);
+        expected.update(&data[..1000]);
+        expected.update(&data[1000..]);
+        expected.finalize();
+        let entropy = expected.get_reports()[0].byte_entropy;
+        let expected: Vec<_> = expected.get_hashes().iter().map(|h| h.unwrap().to_digest()).collect();
+
+        for threads in [1, 2, 3, 8, 16] {
+            let mut builder = ColoredTLSHBuilder::new(&colors);
+            builder.update(&data[..1000]);
+            builder.update_parallel(&data[1000..], threads);
+            builder.finalize();
+            let hashes: Vec<_> = builder.get_hashes().iter().map(|h| h.unwrap().to_digest()).collect();
+            assert_eq!(hashes, expected, "{threads} threads");
+            assert_eq!(builder.get_reports()[0].byte_entropy, entropy);
+        }
+    }
+
+    #[test]
+    fn test_interim_hashes() {
+        let mut rng = crate::util::SplitMix64::new(51);
+        let data = rng.bytes(300_000);
+        let points = [30, 4096, 4100, 70_000, 250_000, 1 << 20];
+        let colors = [0, 7];
+
+        let mut streamed = ColoredTLSHBuilder::new(&colors);
+        streamed.set_interim_points(&points);
+        let mut interim = Vec::new();
+        let mut offset = 0;
+        for len in [1, 10, 1000, 5000, 60_000, 3, 200_000] {
+            streamed.update(&data[offset..offset + len]);
+            offset += len;
+            interim.extend(streamed.take_interim_hashes());
+        }
+        streamed.update_parallel(&data[offset..], 4);
+        interim.extend(streamed.take_interim_hashes());
+
+        assert_eq!(interim.iter().map(|i| i.data_len).collect::<Vec<_>>(), points[..5]);
+        for i in &interim {
+            let expected = ColoredTLSH::hash_colors(&data[..i.data_len as usize], &colors);
+            match expected {
+                Ok(expected) => {
+                    for (hash, expected) in i.hashes.iter().zip(expected) {
+                        assert_eq!(hash.unwrap().to_digest(), expected.to_digest());
+                    }
+                }
+                Err(e) => assert!(i.hashes.iter().all(|hash| hash.unwrap_err() == e)),
+            }
+        }
+
+        // The running state is not disturbed
+        streamed.finalize();
+        let mut whole = ColoredTLSHBuilder::new(&colors);
+        whole.update(&data);
+        whole.finalize();
+        for (a, b) in streamed.get_hashes().iter().zip(whole.get_hashes()) {
+            assert_eq!(a.unwrap().to_digest(), b.unwrap().to_digest());
+        }
+
+        // Points already passed are skipped, the rest is kept by a reset
+        let mut builder = TLSHBuilder::new();
+        builder.update(&data[..5000]);
+        builder.set_interim_points(&[100, 5000, 6000]);
+        builder.update(&data[5000..7000]);
+        assert_eq!(builder.take_interim_hashes().iter().map(|i| i.0).collect::<Vec<_>>(), [6000]);
+        builder.color_builder.reset();
+        builder.update(&data[..7000]);
+        assert_eq!(builder.take_interim_hashes().iter().map(|i| i.0).collect::<Vec<_>>(), [100, 5000, 6000]);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut builder = ColoredTLSHBuilder::new(&[0, 7]);
+        builder.update(&random_bytes[..300]);
+        let blob = builder.to_checkpoint();
+        let mut fork = builder.clone();
+
+        let mut restored = ColoredTLSHBuilder::from_checkpoint(&blob).unwrap();
+        for b in [&mut builder, &mut restored, &mut fork] {
+            for chunk in random_bytes[300..].chunks(77) {
+                b.update(chunk);
+            }
+            b.finalize();
+        }
+        let digests = |b: &ColoredTLSHBuilder| b.get_hashes().iter().map(|h| h.unwrap().to_digest()).collect::<Vec<_>>();
+        assert_eq!(digests(&builder)[0], "009411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110");
+        assert_eq!(digests(&restored), digests(&builder));
+        assert_eq!(digests(&fork), digests(&builder));
+        assert_eq!(restored.get_reports()[0].byte_entropy, builder.get_reports()[0].byte_entropy);
+
+        assert!(TLSHBuilder::from_checkpoint(&blob).is_err_and(|e| e == CheckpointError::InvalidColor));
+        assert_eq!(ColoredTLSHBuilder::from_checkpoint(&blob[1..]).err(), Some(CheckpointError::InvalidMagic));
+        assert_eq!(ColoredTLSHBuilder::from_checkpoint(&blob[..100]).err(), Some(CheckpointError::InvalidLength));
+        let mut future = blob.clone();
+        future[4] = 99;
+        assert_eq!(ColoredTLSHBuilder::from_checkpoint(&future).err(), Some(CheckpointError::UnsupportedVersion));
+        assert_eq!(CheckpointError::InvalidMagic.to_string(), "not a TLSH builder checkpoint");
+    }
+
+    #[test]
+    fn test_huge_lengths() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut builder = ColoredTLSHBuilder::new(&[0]);
+        builder.update(random_bytes);
+        builder.finalize();
+        let mut expected = builder.get_hashes()[0].unwrap();
+
+        // Truncated to 32 bits, this would be a valid length of 705032704 bytes
+        builder.data_len = 5_000_000_000;
+        builder.finalize();
+        assert!(matches!(builder.get_hashes()[0], Err(TLSHError::TooLong { .. })));
+        assert!(matches!(builder.hash_complete(&[1; 10])[0], Err(TLSHError::Length { .. })));
+
+        // The largest representable length, with bucket counts beyond 32 bits. Scaling all counts
+        // keeps the codes and quartile ratios, only the lvalue changes.
+        builder.reset();
+        builder.update(random_bytes);
+        builder.flush_buffer();
+        builder.data_len = 4_224_281_216;
+        for count in builder.colors[0].a_bucket.iter_mut() {
+            *count <<= 32;
+        }
+        builder.finalize();
+        expected.tlsh.lvalue = 169;
+        assert_eq!(builder.get_hashes()[0].unwrap().to_digest(), expected.to_digest());
+
+        let mut restored = ColoredTLSHBuilder::from_checkpoint(&builder.to_checkpoint()).unwrap();
+        restored.finalize();
+        assert_eq!(restored.get_hashes()[0].unwrap().to_digest(), expected.to_digest());
+        restored.update(&[0]);
+        restored.finalize();
+        assert!(matches!(restored.get_hashes()[0], Err(TLSHError::TooLong { .. })));
+    }
+
+    #[test]
+    fn test_options() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let hash = |data: &[u8], options: TLSHOptions| {
+            let mut builder = TLSHBuilder::with_options(options);
+            builder.update(data);
+            builder.finalize();
+            builder.get_hash().map(|h| (h.to_digest(), builder.get_forced()))
+        };
+        let default = hash(random_bytes, TLSHOptions::default()).unwrap();
+        assert_eq!(hash(random_bytes, TLSHOptions::conservative()).unwrap(), default);
+        assert!(!default.1);
+
+        assert!(hash(&random_bytes[..200], TLSHOptions::default()).is_ok());
+        assert!(matches!(hash(&random_bytes[..200], TLSHOptions::conservative()), Err(TLSHError::Length { .. })));
+        let short = TLSHOptions { min_length: 30, force: true };
+        assert!(matches!(hash(&random_bytes[..40], TLSHOptions::default()), Err(TLSHError::Length { .. })));
+        assert!(hash(&random_bytes[..40], short).is_ok());
+
+        // Uses 50 of the 128 buckets
+        let periodic: Vec<u8> = (0..1000).map(|i| (i % 20) as u8 * 7).collect();
+        assert!(matches!(hash(&periodic, TLSHOptions::default()), Err(TLSHError::Variety { .. })));
+        let forced = TLSHOptions { force: true, ..TLSHOptions::default() };
+        let (digest, is_forced) = hash(&periodic, forced).unwrap();
+        assert!(is_forced);
+        assert_eq!(TLSH::from_digest(&digest).lvalue, calc_lvalue(1000).unwrap());
+        assert_eq!(hash(random_bytes, forced).unwrap(), default);
+        assert!(matches!(hash(&[b'A'; 1000], forced), Err(TLSHError::Variety { .. })));
+
+        let mut restored = TLSHBuilder::from_checkpoint(&TLSHBuilder::new().to_checkpoint()).unwrap();
+        restored.update(&periodic);
+        restored.set_options(forced);
+        restored.finalize();
+        assert_eq!(restored.get_hash().unwrap().to_digest(), digest);
+    }
+
+    #[test]
+    fn test_from_buckets() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut builder = ColoredTLSHBuilder::new(&[0, 6]);
+        builder.update(random_bytes);
+        builder.finalize();
+        for report in builder.get_reports() {
+            let hash = report.hash.unwrap().tlsh;
+            let rebuilt = TLSH::from_buckets(&report.buckets, hash.checksum, random_bytes.len() as u64).unwrap();
+            assert_eq!(rebuilt.to_digest(), hash.to_digest());
+        }
+
+        let mut buckets = [0; 256];
+        for (i, count) in buckets.iter_mut().enumerate().take(EFF_BUCKETS) {
+            *count = (i as u64 * 37) % 101;
+        }
+        let hash = TLSH::from_buckets(&buckets, 0x5A, 5000).unwrap();
+        assert_eq!((hash.checksum, hash.lvalue), (0x5A, calc_lvalue(5000).unwrap()));
+        assert_eq!(TLSH::from_buckets(&buckets, 0, 10).unwrap_err(), TLSHError::Length { data_len: 10, min_length: 50 });
+        assert_eq!(TLSH::from_buckets(&[1; 256], 0, 5000).unwrap().codes, [0; 32]);
+        assert!(matches!(TLSH::from_buckets(&[0; 256], 0, 5000), Err(TLSHError::Variety { nonzero: 0, .. })));
+    }
+
+    #[test]
+    fn test_every_chunking() {
+        let files: [(&[u8], &[u8]); 2] = [
+            (include_bytes!("../test/data/random.txt"), &[0, 9]),
+            (include_bytes!("../test/data/y.tlsh.txt"), &[0, 3]),
+        ];
+        let hashes = |builder: &ColoredTLSHBuilder| format!("{:?}", builder.get_hashes());
+        for (data, colors) in files {
+            let mut whole = ColoredTLSHBuilder::new(colors);
+            whole.update(data);
+            whole.finalize();
+            let expected = hashes(&whole);
+
+            for chunk_size in 1..=data.len() {
+                let mut builder = ColoredTLSHBuilder::new(colors);
+                for chunk in data.chunks(chunk_size) {
+                    builder.update(chunk);
+                }
+                builder.finalize();
+                assert_eq!(hashes(&builder), expected, "chunk size {chunk_size}");
+            }
+
+            let mut rng = crate::util::SplitMix64::new(0);
+            for _ in 0..100 {
+                let mut builder = ColoredTLSHBuilder::new(colors);
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let (chunk, tail) = rest.split_at(rng.below(rest.len().min(40) + 1));
+                    builder.update(chunk);
+                    rest = tail;
+                }
+                builder.finalize();
+                assert_eq!(hashes(&builder), expected);
+            }
+        }
+    }
+
     #[test]
     fn test_ys() {
         let y_bytes = include_bytes!("../test/data/y.tlsh.txt");
         let mut tlsh_builder = TLSHBuilder::new();
         tlsh_builder.update(y_bytes);
         tlsh_builder.finalize();
-        assert!(matches!(tlsh_builder.get_hash().unwrap_err(), TLSHError::Variety));
+        assert!(matches!(tlsh_builder.get_hash().unwrap_err(), TLSHError::Variety { .. }));
+    }
+
+    #[test]
+    fn test_independent_colors() {
+        // Around half of the buckets are used, so some colors succeed and others do not
+        let data: Vec<u8> = (0..1000).map(|i| (i % 30) as u8 * 7).collect();
+        let colors: Vec<u8> = (0..16).collect();
+        let mut builder = ColoredTLSHBuilder::new(&colors);
+        builder.update(&data);
+        builder.finalize();
+        let hashes = builder.get_hashes();
+        assert!(hashes[0].is_err());
+        assert!(hashes.iter().any(|h| h.is_ok()));
+        assert_eq!(builder.get_forced(), vec![false; 16]);
+        for (color, hash) in colors.iter().zip(&hashes) {
+            let mut single = ColoredTLSHBuilder::new(&[*color]);
+            single.update(&data);
+            single.finalize();
+            assert_eq!(format!("{:?}", single.get_hashes()[0]), format!("{hash:?}"));
+        }
+
+        let Err(error) = hashes[0] else { unreachable!() };
+        let TLSHError::Variety { data_len, nonzero, quartiles } = error else {
+            panic!("{error:?}")
+        };
+        assert_eq!(data_len, 1000);
+        assert!(nonzero <= 64);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "data of 1000 bytes has too little variety for TLSH: {nonzero} of 128 buckets used, quartiles {}/{}/{}",
+                quartiles[0], quartiles[1], quartiles[2]
+            )
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let mut builder = TLSHBuilder::with_options(TLSHOptions::conservative());
+        builder.update(&[1; 100]);
+        builder.finalize();
+        let error = builder.get_hash().unwrap_err();
+        assert_eq!(error, TLSHError::Length { data_len: 100, min_length: 256 });
+        assert_eq!(error.to_string(), "data too short for TLSH: 100 bytes, at least 256 required");
+        let error: Box<dyn std::error::Error> = Box::new(TLSHError::TooLong { data_len: 1 << 32 });
+        assert_eq!(error.to_string(), "data too long for TLSH: 4294967296 bytes, at most 4224281216 allowed");
     }
     
     #[test]
@@ -531,6 +1340,6 @@ mod tests {
         let mut tlsh_builder = TLSHBuilder::new();
         tlsh_builder.update(&smal);
         tlsh_builder.finalize();
-        assert!(matches!(tlsh_builder.get_hash().unwrap_err(), TLSHError::Length));
+        assert!(matches!(tlsh_builder.get_hash().unwrap_err(), TLSHError::Length { .. }));
     }
 }
@@ -0,0 +1,250 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    hash::TLSH,
+    segment::{Segment, SegmentMatch},
+    ColoredTLSHBuilder,
+};
+
+/// Random values of the bytes for the Gear rolling hash, from SplitMix64 with a fixed seed
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x4745_4152;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Options of content-defined chunking by `ChunkedTLSH`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkOptions {
+    /// Length below which no chunk is cut, except the last one
+    pub min_size: usize,
+    /// Typical length of a chunk
+    pub avg_size: usize,
+    /// Length above which every chunk is cut
+    pub max_size: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            avg_size: 4096,
+            max_size: 16384,
+        }
+    }
+}
+
+/// Result of `ChunkedTLSH::compare`
+#[derive(Clone, Debug)]
+pub struct ChunkComparison {
+    /// The matching chunks, in increasing order of distance
+    pub matches: Vec<SegmentMatch>,
+    /// Fraction of the bytes of the hashed chunks of both data in matching chunks, from 0 to 1
+    pub similarity: f64,
+}
+
+/// TLSH hashes of content-defined chunks of data
+///
+/// Chunks are cut by a Gear rolling hash as in FastCDC, so the cuts follow the content and
+/// survive inserted or deleted bytes, unlike the fixed blocks of `SegmentedTLSH`.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{ChunkOptions, ChunkedTLSH};
+/// let data: Vec<u8> = (0..8000).flat_map(|i| format!("{i:5} {:08x}\n", i * i % 7919).into_bytes()).collect();
+/// let mut edited = data.clone();
+/// edited.splice(5000..5000, [0x90; 700]);
+/// edited.drain(60000..60300);
+///
+/// let a = ChunkedTLSH::hash(&data, ChunkOptions::default());
+/// let b = ChunkedTLSH::hash(&edited, ChunkOptions::default());
+/// assert!(ChunkedTLSH::compare(&a, &b, 50).similarity > 0.8);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChunkedTLSH {
+    pub options: ChunkOptions,
+    pub chunks: Vec<Segment>,
+}
+
+impl ChunkedTLSH {
+    /// Cut data into chunks and calculate their hashes
+    ///
+    /// # Panics
+    ///
+    /// The method panics unless `0 < min_size <= avg_size <= max_size`.
+    pub fn hash(data: &[u8], options: ChunkOptions) -> Self {
+        assert!(
+            0 < options.min_size && options.min_size <= options.avg_size && options.avg_size <= options.max_size,
+            "Invalid chunk options"
+        );
+        let mut builder = ColoredTLSHBuilder::new(&[0]);
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = cut(&data[offset..], options);
+            let chunk = &data[offset..offset + len];
+            chunks.push(Segment {
+                offset: offset as u64,
+                len,
+                hash: builder.hash_complete(chunk)[0].map(|hash| hash.tlsh),
+            });
+            offset += len;
+        }
+        Self { options, chunks }
+    }
+
+    /// Cut the content of a file into chunks and calculate their hashes
+    ///
+    /// See `ChunkedTLSH::hash`
+    pub fn hash_file(path: impl AsRef<Path>, options: ChunkOptions) -> io::Result<Self> {
+        Ok(Self::hash(&fs::read(path)?, options))
+    }
+
+    /// Match the chunks of two data and measure their similarity
+    ///
+    /// Chunks match if the distance of their hashes is at most `max_distance`, closest pairs
+    /// first, each chunk at most once, in any order. Chunks without a hash are left out.
+    pub fn compare(a: &Self, b: &Self, max_distance: i32) -> ChunkComparison {
+        let mut pairs = Vec::new();
+        for (i, ca) in a.chunks.iter().enumerate() {
+            let Ok(ha) = &ca.hash else { continue };
+            for (j, cb) in b.chunks.iter().enumerate() {
+                let Ok(hb) = &cb.hash else { continue };
+                let distance = TLSH::diff(ha, hb);
+                if distance <= max_distance {
+                    pairs.push((distance, i, j));
+                }
+            }
+        }
+        pairs.sort_unstable();
+
+        let mut used_a = vec![false; a.chunks.len()];
+        let mut used_b = vec![false; b.chunks.len()];
+        let mut matches = Vec::new();
+        let mut matched_len = 0;
+        for (distance, i, j) in pairs {
+            if used_a[i] || used_b[j] {
+                continue;
+            }
+            used_a[i] = true;
+            used_b[j] = true;
+            matched_len += a.chunks[i].len + b.chunks[j].len;
+            matches.push(SegmentMatch {
+                offset_a: a.chunks[i].offset,
+                offset_b: b.chunks[j].offset,
+                distance,
+            });
+        }
+
+        let hashed_len: usize = a.chunks.iter().chain(&b.chunks).filter(|c| c.hash.is_ok()).map(|c| c.len).sum();
+        let similarity = if hashed_len == 0 { 0.0 } else { matched_len as f64 / hashed_len as f64 };
+        ChunkComparison { matches, similarity }
+    }
+}
+
+/// Length of the first chunk of data
+fn cut(data: &[u8], options: ChunkOptions) -> usize {
+    if data.len() <= options.min_size {
+        return data.len();
+    }
+    let bits = options.avg_size.ilog2();
+    let mask_small = mask(bits + 1);
+    let mask_large = mask(bits.saturating_sub(1));
+    let end = data.len().min(options.max_size);
+    let normal = end.min(options.avg_size);
+
+    let mut hash: u64 = 0;
+    for (i, byte) in data.iter().enumerate().take(end).skip(options.min_size) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < normal { mask_small } else { mask_large };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Mask of the highest `bits` bits, which depend on the last 64 bytes of the Gear hash
+fn mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits => !0 << (64 - bits.min(64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::SplitMix64;
+
+    #[test]
+    fn test_chunks() {
+        let mut rng = SplitMix64::new(41);
+        let data = rng.bytes(300000);
+        let options = ChunkOptions::default();
+        let chunked = ChunkedTLSH::hash(&data, options);
+
+        let mut offset = 0;
+        for (k, chunk) in chunked.chunks.iter().enumerate() {
+            assert_eq!(chunk.offset, offset);
+            assert!(chunk.len <= options.max_size);
+            assert!(chunk.len >= options.min_size || k == chunked.chunks.len() - 1);
+            let bytes = &data[offset as usize..offset as usize + chunk.len];
+            assert_eq!(chunk.hash.unwrap().to_digest(), TLSH::hash(bytes).unwrap().to_digest());
+            offset += chunk.len as u64;
+        }
+        assert_eq!(offset, data.len() as u64);
+        let average = data.len() / chunked.chunks.len();
+        assert!(average > options.avg_size / 2 && average < options.avg_size * 2, "{average}");
+
+        // The same content is cut at the same places, wherever it is
+        let mut shifted = rng.bytes(1000);
+        shifted.extend_from_slice(&data);
+        let cuts = |chunked: &ChunkedTLSH, shift: u64| {
+            chunked.chunks.iter().map(|c| c.offset + c.len as u64 - shift).collect::<Vec<_>>()
+        };
+        let original = cuts(&chunked, 0);
+        let moved = cuts(&ChunkedTLSH::hash(&shifted, options), 1000);
+        let common = original.iter().filter(|cut| moved.contains(cut)).count();
+        assert!(common >= original.len() - 2);
+
+        assert!(ChunkedTLSH::hash(&[], options).chunks.is_empty());
+        let short = ChunkedTLSH::hash(&data[..500], options);
+        assert_eq!((short.chunks.len(), short.chunks[0].len), (1, 500));
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut rng = SplitMix64::new(42);
+        let data = rng.bytes(200000);
+        let mut edited = data.clone();
+        edited.splice(50000..50000, rng.bytes(3000));
+        edited.drain(120000..121000);
+        edited[150000..150100].fill(0);
+
+        let options = ChunkOptions::default();
+        let a = ChunkedTLSH::hash(&data, options);
+        let b = ChunkedTLSH::hash(&edited, options);
+        let comparison = ChunkedTLSH::compare(&a, &b, 50);
+        assert!(comparison.similarity > 0.9, "{}", comparison.similarity);
+        assert!(comparison.matches.windows(2).all(|w| w[0].distance <= w[1].distance));
+        let exact = comparison.matches.iter().filter(|m| m.distance == 0).count();
+        assert!(exact > a.chunks.len() * 3 / 4);
+
+        let unrelated = ChunkedTLSH::hash(&rng.bytes(200000), options);
+        assert!(ChunkedTLSH::compare(&a, &unrelated, 50).similarity < 0.05);
+        assert_eq!(ChunkedTLSH::compare(&a, &a, 0).similarity, 1.0);
+    }
+}
@@ -3,12 +3,16 @@ use crate::{ColoredTLSH, hash::TLSH, util::mod_diff};
 #[derive(Debug, Clone, PartialEq)]
 pub enum TLSHDiffError {
     ColorMismatch,
+    /// The hashes are of keyed colors of different keys
+    KeyMismatch,
 }
 
 impl ColoredTLSH {
     pub fn try_diff(a: &Self, b: &Self) -> Result<i32, TLSHDiffError> {
         if a.color != b.color {
             Err(TLSHDiffError::ColorMismatch)
+        } else if a.key_id != b.key_id {
+            Err(TLSHDiffError::KeyMismatch)
         } else {
             Ok(TLSH::diff(&a.tlsh, &b.tlsh))
         }
@@ -22,10 +26,19 @@ impl ColoredTLSH {
 impl TLSH {
     /// Calculate the TLSH difference of two hash objects
     pub fn diff(a: &Self, b: &Self) -> i32 {
-        (Self::diff_checksum(a, b)
-            + Self::diff_lvalue(a, b)
-            + Self::diff_q_ratios(a, b)
-            + Self::diff_codes(a, b)) as i32
+        (Self::diff_header(a, b) + Self::diff_codes(a, b)) as i32
+    }
+    /// Calculate the TLSH difference of two hash objects, ignoring the lengths of their data
+    ///
+    /// The same as `diff` without the difference of the lvalues, like the distance of the
+    /// reference implementation without length difference. Use it to compare hashes of data of
+    /// very different lengths, e.g. a prefix of a file with whole files.
+    pub fn diff_without_length(a: &Self, b: &Self) -> i32 {
+        (Self::diff_checksum(a, b) + Self::diff_q_ratios(a, b) + Self::diff_codes(a, b)) as i32
+    }
+    /// The difference of the checksums, lvalues and q-ratios
+    pub(crate) fn diff_header(a: &Self, b: &Self) -> u32 {
+        Self::diff_checksum(a, b) + Self::diff_lvalue(a, b) + Self::diff_q_ratios(a, b)
     }
     fn diff_checksum(a: &Self, b: &Self) -> u32 {
         if a.checksum == b.checksum {
@@ -4,11 +4,14 @@ use crate::ColoredTLSH;
 use crate::hash::TLSH;
 
 const BODY_SIZE: usize = 32;
-const HASH_SIZE: usize = 3 + BODY_SIZE;
+pub(crate) const HASH_SIZE: usize = 3 + BODY_SIZE;
 const HEX_HASH_SIZE: usize = HASH_SIZE * 2;
 const VERSIONED_HEX_HASH_SIZE: usize = HEX_HASH_SIZE + 2;
 const COLORED_HASH_SIZE: usize = 1 + HASH_SIZE;
 const HEX_COLORED_HASH_SIZE: usize = COLORED_HASH_SIZE * 2;
+/// A colored hash with the 32-bit key id of a keyed color after the marker
+const KEYED_HASH_SIZE: usize = COLORED_HASH_SIZE + 4;
+const HEX_KEYED_HASH_SIZE: usize = KEYED_HASH_SIZE * 2;
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq)]
@@ -92,23 +95,27 @@ impl TLSH {
 }
 
 impl ColoredTLSH {
-    /// Exports the colored hash object to its 36-byte representation
-    pub fn to_raw(&self) -> [u8; COLORED_HASH_SIZE] {
-        let mut raw = [0u8; COLORED_HASH_SIZE];
-        raw[0] = self.color;
-        let tlsh = self.tlsh.to_raw();
-        raw[1..].copy_from_slice(&tlsh);
+    /// Exports the colored hash object to its 36-byte representation, 40 bytes with a key id
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = vec![self.color];
+        if let Some(key_id) = self.key_id {
+            raw.extend_from_slice(&key_id.to_be_bytes());
+        }
+        raw.extend_from_slice(&self.tlsh.to_raw());
         raw
     }
 
-    /// Tries to import a hash object from its raw 35-byte representation
+    /// Tries to import a hash object from its raw 36-byte or 40-byte representation
     pub fn try_from_raw(raw: &[u8]) -> Result<Self, TLSHDigestError> {
-        if raw.len() != COLORED_HASH_SIZE {
-            return Err(TLSHDigestError::InvalidLength);
-        }
-        let tlsh = TLSH::try_from_raw(&raw[1..])?;
+        let (key_id, tlsh) = match raw.len() {
+            COLORED_HASH_SIZE => (None, &raw[1..]),
+            KEYED_HASH_SIZE => (Some(u32::from_be_bytes(raw[1..5].try_into().unwrap())), &raw[5..]),
+            _ => return Err(TLSHDigestError::InvalidLength),
+        };
+        let tlsh = TLSH::try_from_raw(tlsh)?;
         Ok(Self {
             color: raw[0],
+            key_id,
             tlsh,
         })
     }
@@ -127,8 +134,8 @@ impl ColoredTLSH {
 
     /// Tries to import a ColoredTLSH object from a digest string
     ///
-    /// It supports loading the standard 70, the T1 versioned and the 72 long
-    /// colored TLSH digests
+    /// It supports loading the standard 70, the T1 versioned, the 72 long
+    /// colored and the 80 long keyed TLSH digests
     pub fn try_from_digest(digest: &str) -> Result<Self, TLSHDigestError> {
         let (color, digest) = match digest.len() {
             HEX_HASH_SIZE => (0, digest),
@@ -138,12 +145,17 @@ impl ColoredTLSH {
                 let color = <[u8;1]>::from_hex(&digest[..2]).map_err(|_| TLSHDigestError::InvalidHex)?[0];
                 (color, &digest[2..])
             },
+            HEX_KEYED_HASH_SIZE => {
+                let raw = hex::decode(digest).map_err(|_| TLSHDigestError::InvalidHex)?;
+                return Self::try_from_raw(&raw);
+            },
             _ => return Err(TLSHDigestError::InvalidLength),
         };
         let hash_bytes = hex::decode(digest).map_err(|_| TLSHDigestError::InvalidHex)?;
         let tlsh = TLSH::try_from_raw(&hash_bytes)?;
         Ok(Self {
             color,
+            key_id: None,
             tlsh,
         })
     }
@@ -0,0 +1,184 @@
+use crate::hash::{ColoredTLSH, EFF_BUCKETS, TLSH};
+
+const BODY_BITS: usize = EFF_BUCKETS * 3;
+const LVALUE_BITS: usize = 128;
+const Q_RATIO_BITS: usize = 8;
+const LVALUE_OFFSET: usize = BODY_BITS;
+const Q1_OFFSET: usize = LVALUE_OFFSET + LVALUE_BITS;
+const Q2_OFFSET: usize = Q1_OFFSET + Q_RATIO_BITS;
+const HEADER_WEIGHT: f32 = 12.0;
+
+/// Number of dimensions of `TLSH::to_embedding`
+pub const EMBEDDING_DIM: usize = Q2_OFFSET + Q_RATIO_BITS;
+/// Size in bytes of `TLSH::to_hamming_code`
+pub const HAMMING_CODE_SIZE: usize = EMBEDDING_DIM / 8;
+
+/// Calls `set` with the index of every 1 bit of the binary code of a hash
+///
+/// * Each 2-bit bucket code `c` is unary coded on 3 bits (`c` ones), so the Hamming
+///   distance of two buckets is the absolute difference of their codes.
+/// * The lvalue (mod 256) and the two q-ratios (mod 16) are coded on half as many bits as their
+///   modulus, bit `k` being set iff `(v - k) mod m < m / 2`. The Hamming distance of two such
+///   codes is the circular distance `mod_diff` of the values.
+fn for_each_set_bit(tlsh: &TLSH, mut set: impl FnMut(usize)) {
+    for bucket in 0..EFF_BUCKETS {
+        let code = (tlsh.codes[bucket / 4] >> ((bucket % 4) * 2)) & 3;
+        for t in 0..code as usize {
+            set(bucket * 3 + t);
+        }
+    }
+    let circular = |value: u8, modulus: usize, offset: usize, set: &mut dyn FnMut(usize)| {
+        for k in 0..modulus / 2 {
+            if (value as usize + modulus - k) % modulus < modulus / 2 {
+                set(offset + k);
+            }
+        }
+    };
+    circular(tlsh.lvalue, 2 * LVALUE_BITS, LVALUE_OFFSET, &mut set);
+    circular(tlsh.q_ratios & 0xf, 2 * Q_RATIO_BITS, Q1_OFFSET, &mut set);
+    circular(tlsh.q_ratios >> 4, 2 * Q_RATIO_BITS, Q2_OFFSET, &mut set);
+}
+
+impl TLSH {
+    /// Converts the hash into a fixed-length vector whose L1 distance approximates `TLSH::diff`
+    ///
+    /// The vector is the binary code of `to_hamming_code` with the lvalue and q-ratio dimensions
+    /// weighted by 12, the multiplier `TLSH::diff` uses for them. The approximation differs from
+    /// `TLSH::diff` in that
+    ///
+    /// * the checksum is left out (at most 1 less),
+    /// * a bucket difference of 3 counts 3 instead of 6,
+    /// * an lvalue or q-ratio difference of 1 counts 12 instead of 1,
+    /// * a q-ratio difference `d > 1` counts `12 * d` instead of `12 * (d - 1)`.
+    ///
+    /// On 2000 pairs of random files of 1-64 KiB over 24 byte values, half of them unrelated, half
+    /// of them related by overwriting up to 40% of the bytes, the mean absolute error was 10.1%
+    /// of the real distance, 16.5 for the 470 pairs within 100. See the ignored test
+    /// `measure_approximation`.
+    pub fn to_embedding(&self) -> [f32; EMBEDDING_DIM] {
+        let mut v = [0.0; EMBEDDING_DIM];
+        for_each_set_bit(self, |i| {
+            v[i] = if i < BODY_BITS { 1.0 } else { HEADER_WEIGHT };
+        });
+        v
+    }
+
+    /// Converts the hash into a binary code whose Hamming distance approximates `TLSH::diff`
+    ///
+    /// The Hamming distance equals the body distance of `TLSH::diff` except for bucket
+    /// differences of 3 (counted as 3 instead of 6), plus the unweighted circular differences of
+    /// the lvalue and the q-ratios. As the header is not weighted, it is best suited for finding
+    /// candidates among hashes of similar length. On the pairs of `to_embedding`, which mix all
+    /// lengths, the Hamming distance was 48% below the real distance.
+    pub fn to_hamming_code(&self) -> [u8; HAMMING_CODE_SIZE] {
+        let mut code = [0u8; HAMMING_CODE_SIZE];
+        for_each_set_bit(self, |i| code[i / 8] |= 1 << (i % 8));
+        code
+    }
+}
+
+impl ColoredTLSH {
+    /// Converts the hash into a vector, see `TLSH::to_embedding`
+    ///
+    /// The color is not encoded, only compare vectors of the same color.
+    pub fn to_embedding(&self) -> [f32; EMBEDDING_DIM] {
+        self.tlsh.to_embedding()
+    }
+
+    /// Converts the hash into a binary code, see `TLSH::to_hamming_code`
+    ///
+    /// The color is not encoded, only compare codes of the same color.
+    pub fn to_hamming_code(&self) -> [u8; HAMMING_CODE_SIZE] {
+        self.tlsh.to_hamming_code()
+    }
+}
+
+/// L1 (Manhattan) distance of two embedding vectors
+pub fn l1_distance(a: &[f32; EMBEDDING_DIM], b: &[f32; EMBEDDING_DIM]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+}
+
+/// Hamming distance of two binary codes
+pub fn hamming_distance(a: &[u8; HAMMING_CODE_SIZE], b: &[u8; HAMMING_CODE_SIZE]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::SplitMix64;
+
+    #[test]
+    fn test_embedding_distances() {
+        let a = TLSH::from_digest("53152333A0D13738E4B172B10F6AC6135BEF7A225664750839D69F8D8E3B6C8D56932C");
+        let b = TLSH::from_digest("94052217B1A73B39E46588F54EA5C09C2CFF3F222934210EB1ACA9491F7F7C0955A792");
+        assert_eq!(l1_distance(&a.to_embedding(), &a.to_embedding()), 0.0);
+        assert_eq!(hamming_distance(&a.to_hamming_code(), &a.to_hamming_code()), 0);
+
+        let mut same_header = b;
+        same_header.checksum = a.checksum;
+        same_header.lvalue = a.lvalue;
+        same_header.q_ratios = a.q_ratios;
+        let body_diff = TLSH::diff(&a, &same_header);
+        let hamming = hamming_distance(&a.to_hamming_code(), &same_header.to_hamming_code()) as i32;
+        let l1 = l1_distance(&a.to_embedding(), &same_header.to_embedding()) as i32;
+        // Only bucket differences of 3 are undercounted, by 3 each
+        assert_eq!(hamming, l1);
+        assert!(hamming <= body_diff && (body_diff - hamming) % 3 == 0);
+    }
+
+    #[test]
+    fn test_circular_header() {
+        let a = TLSH::from_digest("53152333A0D13738E4B172B10F6AC6135BEF7A225664750839D69F8D8E3B6C8D56932C");
+        for (lvalue, distance) in [(0u8, 0u32), (1, 1), (5, 5), (128, 128), (250, 6)] {
+            let mut x = a;
+            let mut y = a;
+            x.lvalue = 0;
+            y.lvalue = lvalue;
+            assert_eq!(hamming_distance(&x.to_hamming_code(), &y.to_hamming_code()), distance);
+        }
+        let mut x = a;
+        let mut y = a;
+        x.q_ratios = 0x0f;
+        y.q_ratios = 0xf1;
+        // q1: 15 vs 1 is 2 apart, q2: 0 vs 15 is 1 apart
+        assert_eq!(l1_distance(&x.to_embedding(), &y.to_embedding()), 36.0);
+    }
+
+    /// Prints the errors of the approximations quoted by `TLSH::to_embedding` and
+    /// `TLSH::to_hamming_code`, run by `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore = "measurement"]
+    fn measure_approximation() {
+        let mut rng = SplitMix64::new(27);
+        let (mut real, mut l1_error, mut hamming) = (0.0, 0.0, 0.0);
+        let (mut close, mut close_error) = (0, 0.0);
+        const PAIRS: usize = 2000;
+        for pair in 0..PAIRS {
+            let len = 1024 + rng.below(63 * 1024);
+            let a = rng.weighted(len);
+            let b = if pair % 2 == 0 {
+                let len = 1024 + rng.below(63 * 1024);
+                rng.weighted(len)
+            } else {
+                let mut b = a.clone();
+                let count = rng.below(len * 2 / 5 + 1);
+                rng.mutate(&mut b, count);
+                b
+            };
+            let (a, b) = (TLSH::hash(&a).unwrap(), TLSH::hash(&b).unwrap());
+            let distance = TLSH::diff(&a, &b) as f64;
+            let error = (l1_distance(&a.to_embedding(), &b.to_embedding()) as f64 - distance).abs();
+            real += distance;
+            l1_error += error;
+            hamming += hamming_distance(&a.to_hamming_code(), &b.to_hamming_code()) as f64;
+            if distance <= 100.0 {
+                close += 1;
+                close_error += error;
+            }
+        }
+        println!("embedding: mean absolute error {:.1}% of the distance", 100.0 * l1_error / real);
+        println!("embedding: {close} pairs within 100, off by {:.1} on average", close_error / close as f64);
+        println!("hamming code: {:.0}% below the distance", 100.0 * (1.0 - hamming / real));
+    }
+}
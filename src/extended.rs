@@ -0,0 +1,205 @@
+use hex::{FromHex, ToHex};
+
+use crate::{
+    builder::TLSHError,
+    digest::{TLSHDigestError, HASH_SIZE},
+    hash::{EFF_BUCKETS, TLSH},
+    report::TLSHReport,
+};
+
+/// Size of `ExtendedTLSH::to_raw`
+pub const EXTENDED_HASH_SIZE: usize = HASH_SIZE + EFF_BUCKETS;
+/// Level difference corresponding to a code difference of 1
+const LEVELS_PER_CODE: f64 = 64.0;
+
+/// A TLSH hash extended with an 8-bit level of each bucket
+///
+/// The level of a bucket is twice its rank among the 128 bucket counts, ties getting the mean of
+/// their ranks, so levels range from 0 to 254 and a quarter of the buckets spans 64 levels, like
+/// a code of the standard hash. Unlike codes, levels keep the order of the buckets within a
+/// quarter. `downgrade` gives the standard hash.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{ExtendedTLSH, TLSHBuilder, TLSH};
+/// let data = include_bytes!("../test/data/random.txt");
+/// let mut changed = data.to_vec();
+/// changed[100..300].fill(0);
+/// let hash = |data: &[u8]| {
+///     let mut builder = TLSHBuilder::new();
+///     builder.update(data);
+///     builder.finalize();
+///     builder.get_extended_hash().unwrap()
+/// };
+/// let (a, b) = (hash(data), hash(&changed));
+/// let distance = ExtendedTLSH::diff(&a, &b);
+/// assert!(distance > 0.0);
+/// assert!((distance - TLSH::diff(&a.downgrade(), &b.downgrade()) as f64).abs() < 30.0);
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct ExtendedTLSH {
+    pub tlsh: TLSH,
+    pub levels: [u8; EFF_BUCKETS],
+}
+
+impl ExtendedTLSH {
+    /// Calculate an extended hash from bucket counts computed elsewhere
+    ///
+    /// The extended counterpart of `TLSH::from_buckets`.
+    pub fn from_buckets(buckets: &[u64; 256], checksum: u8, data_len: u64) -> Result<Self, TLSHError> {
+        let tlsh = TLSH::from_buckets(buckets, checksum, data_len)?;
+        Ok(Self::from_hash(&tlsh, buckets))
+    }
+
+    /// The extended hash of a report of a successful hash calculation
+    pub(crate) fn from_report(report: &TLSHReport) -> Result<Self, TLSHError> {
+        report.hash.map(|hash| Self::from_hash(&hash.tlsh, &report.buckets))
+    }
+
+    fn from_hash(tlsh: &TLSH, buckets: &[u64; 256]) -> Self {
+        let mut sorted: [u64; EFF_BUCKETS] = buckets[..EFF_BUCKETS].try_into().unwrap();
+        sorted.sort_unstable();
+        let mut levels = [0; EFF_BUCKETS];
+        for (level, count) in levels.iter_mut().zip(buckets) {
+            let less = sorted.partition_point(|c| c < count);
+            let equal = sorted.partition_point(|c| c <= count) - less;
+            // Twice the mean rank of the tied counts
+            *level = (2 * less + equal - 1) as u8;
+        }
+        Self { tlsh: *tlsh, levels }
+    }
+
+    /// The standard hash of the same data
+    pub fn downgrade(&self) -> TLSH {
+        self.tlsh
+    }
+
+    /// Calculate the difference of two extended hashes, on the scale of `TLSH::diff`
+    ///
+    /// The header is compared as by `TLSH::diff`, a bucket contributes its level difference in
+    /// codes, `|a - b| / 64`, growing to 6 at 3 like a code difference of 3.
+    pub fn diff(a: &Self, b: &Self) -> f64 {
+        let header = TLSH::diff_header(&a.tlsh, &b.tlsh) as f64;
+        let body: f64 = a
+            .levels
+            .iter()
+            .zip(&b.levels)
+            .map(|(x, y)| {
+                let d = x.abs_diff(*y) as f64 / LEVELS_PER_CODE;
+                if d <= 2.0 {
+                    d
+                } else {
+                    (2.0 + 4.0 * (d - 2.0)).min(6.0)
+                }
+            })
+            .sum();
+        header + body
+    }
+
+    /// Exports the hash object as its raw representation, the raw standard hash followed by the
+    /// levels
+    pub fn to_raw(&self) -> [u8; EXTENDED_HASH_SIZE] {
+        let mut raw = [0; EXTENDED_HASH_SIZE];
+        raw[..HASH_SIZE].copy_from_slice(&self.tlsh.to_raw());
+        raw[HASH_SIZE..].copy_from_slice(&self.levels);
+        raw
+    }
+
+    /// Imports a hash object from its raw representation
+    pub fn try_from_raw(raw: &[u8]) -> Result<Self, TLSHDigestError> {
+        if raw.len() != EXTENDED_HASH_SIZE {
+            return Err(TLSHDigestError::InvalidLength);
+        }
+        Ok(Self {
+            tlsh: TLSH::try_from_raw(&raw[..HASH_SIZE])?,
+            levels: raw[HASH_SIZE..].try_into().unwrap(),
+        })
+    }
+
+    /// Exports the hash object as a hex digest string
+    pub fn to_digest(&self) -> String {
+        self.to_raw().encode_hex_upper()
+    }
+
+    /// Tries to import a hash object from a digest string
+    pub fn try_from_digest(digest: &str) -> Result<Self, TLSHDigestError> {
+        if digest.len() != EXTENDED_HASH_SIZE * 2 {
+            return Err(TLSHDigestError::InvalidLength);
+        }
+        let raw = Vec::<u8>::from_hex(digest).map_err(|_| TLSHDigestError::InvalidHex)?;
+        Self::try_from_raw(&raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::SplitMix64, ColoredTLSHBuilder, TLSHBuilder};
+
+    fn text(rng: &mut SplitMix64, len: usize) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"etaoin shrdlu cmfwyp vbgkqj xz ETAOIN\n";
+        (0..len).map(|_| ALPHABET[rng.below(ALPHABET.len())]).collect()
+    }
+
+    fn hash_extended(data: &[u8]) -> ExtendedTLSH {
+        let mut builder = TLSHBuilder::new();
+        builder.update(data);
+        builder.finalize();
+        builder.get_extended_hash().unwrap()
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut builder = ColoredTLSHBuilder::new(&[0, 4]);
+        builder.update(random_bytes);
+        builder.finalize();
+        for (hash, extended) in builder.get_hashes().iter().zip(builder.get_extended_hashes()) {
+            let extended = extended.unwrap();
+            assert_eq!(extended.downgrade().to_digest(), hash.unwrap().tlsh.to_digest());
+            assert_eq!(ExtendedTLSH::diff(&extended, &extended), 0.0);
+            let parsed = ExtendedTLSH::try_from_digest(&extended.to_digest()).unwrap();
+            assert_eq!(parsed.to_digest(), extended.to_digest());
+            // Tied buckets share the mean of their ranks, so the ranks 0..128 still add up
+            let sum: u32 = extended.levels.iter().map(|level| *level as u32).sum();
+            assert_eq!(sum, 127 * 128);
+        }
+        let report = builder.get_reports().remove(0);
+        let hash = report.hash.unwrap().tlsh;
+        assert_eq!(
+            ExtendedTLSH::from_buckets(&report.buckets, hash.checksum, 1024).unwrap().to_digest(),
+            builder.get_extended_hashes()[0].unwrap().to_digest()
+        );
+
+        let mut buckets = [0; 256];
+        buckets[..EFF_BUCKETS].copy_from_slice(&[[1, 5, 5, 9]; EFF_BUCKETS / 4].concat());
+        let extended = ExtendedTLSH::from_buckets(&buckets, 0, 100).unwrap();
+        assert_eq!(extended.levels[..4], [31, 127, 127, 223]);
+        assert!(ExtendedTLSH::try_from_raw(&[0; 10]).is_err());
+        assert!(ExtendedTLSH::try_from_digest(&"X".repeat(2 * EXTENDED_HASH_SIZE)).is_err());
+    }
+
+    #[test]
+    fn test_compatible_distance() {
+        let mut rng = SplitMix64::new(21);
+        let (mut extended, mut standard, mut deviation) = (0.0, 0.0, 0.0);
+        const PAIRS: usize = 200;
+        for _ in 0..PAIRS {
+            let len = 1024 << rng.below(5);
+            let a = text(&mut rng, len);
+            let mut b = a.clone();
+            for _ in 0..rng.below(len * 2 / 5) {
+                let i = rng.below(len);
+                b[i] = text(&mut rng, 1)[0];
+            }
+            let (a, b) = (hash_extended(&a), hash_extended(&b));
+            let (e, s) = (ExtendedTLSH::diff(&a, &b), TLSH::diff(&a.downgrade(), &b.downgrade()) as f64);
+            extended += e;
+            standard += s;
+            deviation += (e - s).abs();
+        }
+        assert!(extended > standard && extended < 1.15 * standard);
+        assert!(deviation / (PAIRS as f64) < 15.0);
+    }
+}
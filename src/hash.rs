@@ -21,5 +21,7 @@ pub struct TLSH {
 #[derive(Copy, Clone, Debug)]
 pub struct ColoredTLSH {
     pub color: u8,
+    /// Fingerprint of the key of a keyed color, see `KeyedColor`
+    pub key_id: Option<u32>,
     pub tlsh: TLSH,
 }
\ No newline at end of file
@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::{ColoredTLSH, ColoredTLSHBuilder, TLSHBuilder, TLSHError, TLSH};
+
+const READ_BUFFER_SIZE: usize = 1 << 16;
+const MMAP_SLICE_SIZE: usize = 1 << 24;
+
+impl Write for ColoredTLSHBuilder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for TLSHBuilder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ColoredTLSHBuilder {
+    /// Add all data of a reader to process
+    ///
+    /// Returns the number of bytes read.
+    pub fn update_from_reader(&mut self, mut reader: impl Read) -> io::Result<u64> {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(total),
+                Ok(n) => {
+                    self.update(&buffer[..n]);
+                    total += n as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Add the content of a file to process
+    ///
+    /// The file is read through a buffer. Returns the number of bytes read.
+    pub fn update_from_file(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.update_from_reader(File::open(path)?)
+    }
+
+    /// Add the content of a file to process through a memory map
+    ///
+    /// Regular files are memory-mapped and processed in 16 MiB slices, without copying them into
+    /// memory. Pipes, special files and files that cannot be mapped are read through a buffer
+    /// instead.
+    ///
+    /// Returns the number of bytes processed.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it is being hashed, by this or any other
+    /// process. Reading a mapped file that changes is undefined behavior.
+    pub unsafe fn update_from_file_mmap(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        let file = File::open(path)?;
+        if !file.metadata()?.is_file() {
+            return self.update_from_reader(file);
+        }
+        // SAFETY: the mapping is read-only and only lives during this call, the caller guarantees
+        // that the file is not modified meanwhile
+        let mmap = match unsafe { Mmap::map(&file) } {
+            Ok(mmap) => mmap,
+            Err(_) => return self.update_from_reader(file),
+        };
+        #[cfg(unix)]
+        let _ = mmap.advise(memmap2::Advice::Sequential);
+        for slice in mmap.chunks(MMAP_SLICE_SIZE) {
+            self.update(slice);
+        }
+        Ok(mmap.len() as u64)
+    }
+}
+
+impl TLSHBuilder {
+    /// Add all data of a reader to process
+    ///
+    /// Returns the number of bytes read.
+    pub fn update_from_reader(&mut self, reader: impl Read) -> io::Result<u64> {
+        self.color_builder.update_from_reader(reader)
+    }
+
+    /// Add the content of a file to process
+    ///
+    /// See `ColoredTLSHBuilder::update_from_file`
+    pub fn update_from_file(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.color_builder.update_from_file(path)
+    }
+
+    /// Add the content of a file to process through a memory map
+    ///
+    /// # Safety
+    ///
+    /// See `ColoredTLSHBuilder::update_from_file_mmap`
+    pub unsafe fn update_from_file_mmap(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        // SAFETY: forwarded to the caller
+        unsafe { self.color_builder.update_from_file_mmap(path) }
+    }
+}
+
+impl TLSH {
+    /// Calculate the TLSH hash of data in one step
+    pub fn hash(data: &[u8]) -> Result<TLSH, TLSHError> {
+        let mut builder = TLSHBuilder::new();
+        builder.update(data);
+        builder.finalize();
+        builder.get_hash()
+    }
+}
+
+impl ColoredTLSH {
+    /// Calculate the TLSH hashes of data with the given colors in one step
+    ///
+    /// Fails with the first error if any of the colors cannot be calculated.
+    pub fn hash_colors(data: &[u8], colors: &[u8]) -> Result<Vec<ColoredTLSH>, TLSHError> {
+        let mut builder = ColoredTLSHBuilder::new(colors);
+        builder.update(data);
+        builder.finalize();
+        builder.get_hashes().into_iter().collect()
+    }
+}
+
+/// Calculate the TLSH hash of all data of a reader
+///
+/// The outer result reports I/O errors, the inner one the outcome of the hash calculation.
+pub fn hash_reader(reader: impl Read) -> io::Result<Result<TLSH, TLSHError>> {
+    let mut builder = TLSHBuilder::new();
+    builder.update_from_reader(reader)?;
+    builder.finalize();
+    Ok(builder.get_hash())
+}
+
+/// Calculate the TLSH hash of a file, reading it through a buffer
+///
+/// The outer result reports I/O errors, the inner one the outcome of the hash calculation.
+pub fn hash_file(path: impl AsRef<Path>) -> io::Result<Result<TLSH, TLSHError>> {
+    let mut builder = TLSHBuilder::new();
+    builder.update_from_file(path)?;
+    builder.finalize();
+    Ok(builder.get_hash())
+}
+
+/// Calculate the TLSH hash of a file through a memory map
+///
+/// The outer result reports I/O errors, the inner one the outcome of the hash calculation.
+///
+/// # Safety
+///
+/// See `ColoredTLSHBuilder::update_from_file_mmap`
+pub unsafe fn hash_file_mmap(path: impl AsRef<Path>) -> io::Result<Result<TLSH, TLSHError>> {
+    let mut builder = TLSHBuilder::new();
+    // SAFETY: forwarded to the caller
+    unsafe { builder.update_from_file_mmap(path)? };
+    builder.finalize();
+    Ok(builder.get_hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANDOM_DIGEST: &str = "9411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110";
+
+    #[test]
+    fn test_one_shot() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        assert_eq!(TLSH::hash(random_bytes).unwrap().to_digest(), RANDOM_DIGEST);
+        let colored = ColoredTLSH::hash_colors(random_bytes, &[0, 1]).unwrap();
+        assert_eq!(colored[0].tlsh.to_digest(), RANDOM_DIGEST);
+        assert_eq!(colored[1].color, 1);
+        assert!(matches!(TLSH::hash(&[0; 32]), Err(TLSHError::Length { .. })));
+    }
+
+    #[test]
+    fn test_streaming() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut builder = TLSHBuilder::new();
+        io::copy(&mut &random_bytes[..], &mut builder).unwrap();
+        builder.finalize();
+        assert_eq!(builder.get_hash().unwrap().to_digest(), RANDOM_DIGEST);
+
+        assert_eq!(hash_reader(&random_bytes[..]).unwrap().unwrap().to_digest(), RANDOM_DIGEST);
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/data/random.txt");
+        assert_eq!(hash_file(path).unwrap().unwrap().to_digest(), RANDOM_DIGEST);
+        assert!(hash_file("test/data/missing").is_err());
+    }
+
+    #[test]
+    fn test_file_modes() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/data/random.txt");
+        let mut mapped = ColoredTLSHBuilder::new(&[0, 5]);
+        // SAFETY: the test data is not modified
+        assert_eq!(unsafe { mapped.update_from_file_mmap(path) }.unwrap(), 1024);
+        let mut buffered = ColoredTLSHBuilder::new(&[0, 5]);
+        assert_eq!(buffered.update_from_reader(File::open(path).unwrap()).unwrap(), 1024);
+        mapped.finalize();
+        buffered.finalize();
+        for (m, b) in mapped.get_hashes().iter().zip(buffered.get_hashes()) {
+            assert_eq!(m.unwrap().to_digest(), b.unwrap().to_digest());
+        }
+
+        // SAFETY: the test data is not modified
+        assert_eq!(unsafe { hash_file_mmap(path) }.unwrap().unwrap().to_digest(), RANDOM_DIGEST);
+        #[cfg(unix)]
+        assert!(matches!(unsafe { hash_file_mmap("/dev/null") }.unwrap(), Err(TLSHError::Length { .. })));
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::util::{siphash24, Pearson};
+
+/// Smallest marker of a keyed color, the markers below are the numbers of the standard colors
+pub const MIN_KEYED_MARKER: u8 = 0x10;
+
+/// A hash color derived from a secret key
+///
+/// The Pearson table is shuffled by SipHash-2-4 of the key, so data cannot be crafted against
+/// the hash without the key.
+///
+/// The marker and a 32-bit key id, both derived from the key, are stored in `ColoredTLSH` and its
+/// digests, so `ColoredTLSH::try_diff` refuses to compare hashes of different keys even if their
+/// markers coincide. A builder rejects keyed colors of the same marker.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{ColoredTLSH, ColoredTLSHBuilder, KeyedColor, TLSHOptions};
+/// let hash = |color: KeyedColor| {
+///     let mut builder = ColoredTLSHBuilder::with_keyed_colors(&[color], TLSHOptions::default());
+///     builder.update(include_bytes!("../test/data/random.txt"));
+///     builder.finalize();
+///     builder.get_hashes()[0].unwrap()
+/// };
+/// let color = KeyedColor::new(b"0123456789abcdef");
+/// let ours = hash(color.clone());
+/// let theirs = hash(KeyedColor::new(b"fedcba9876543210"));
+/// assert_eq!(ours.color, color.marker());
+/// assert!(ours.to_digest().starts_with(&format!("{:02X}", color.marker())));
+/// assert!(ColoredTLSH::try_diff(&ours, &theirs).is_err());
+/// ```
+#[derive(Clone)]
+pub struct KeyedColor {
+    pub(crate) pearson: Pearson,
+}
+
+impl KeyedColor {
+    /// Derive a keyed color from a 128-bit secret key
+    pub fn new(key: &[u8; 16]) -> Self {
+        let marker = MIN_KEYED_MARKER + (siphash24(key, b"TLSH marker") % (256 - MIN_KEYED_MARKER as u64)) as u8;
+        let mut pearson = Pearson::keyed(key, marker);
+        pearson.key_id = Some(siphash24(key, b"TLSH key id") as u32);
+        Self { pearson }
+    }
+
+    /// The marker of the color, the color number of its hashes
+    pub fn marker(&self) -> u8 {
+        self.pearson.color
+    }
+
+    /// The fingerprint of the key, stored in the hashes of the color
+    pub fn key_id(&self) -> u32 {
+        self.pearson.key_id.unwrap()
+    }
+}
+
+/// Panics if two of `colors` have the same marker
+pub(crate) fn assert_distinct_markers(colors: &[KeyedColor]) {
+    for (i, color) in colors.iter().enumerate() {
+        assert!(
+            colors[i + 1..].iter().all(|other| other.marker() != color.marker()),
+            "Keyed colors of the same marker"
+        );
+    }
+}
+
+impl fmt::Debug for KeyedColor {
+    /// Shows the marker only, the permutation would reveal information of the key
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedColor").field("marker", &self.marker()).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff::TLSHDiffError, CheckpointError, ColoredTLSH, ColoredTLSHBuilder, TLSHOptions};
+
+    fn hashes(data: &[u8], colors: &[KeyedColor]) -> Vec<ColoredTLSH> {
+        let mut builder = ColoredTLSHBuilder::with_keyed_colors(colors, TLSHOptions::default());
+        builder.update(data);
+        builder.finalize();
+        builder.get_hashes().into_iter().map(|hash| hash.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_keyed_colors() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let colors = [KeyedColor::new(&[1; 16]), KeyedColor::new(&[2; 16]), KeyedColor::new(&[3; 16])];
+        let markers: Vec<u8> = colors.iter().map(KeyedColor::marker).collect();
+        assert!(markers.iter().all(|marker| *marker >= MIN_KEYED_MARKER));
+        assert_eq!(KeyedColor::new(&[1; 16]).marker(), markers[0]);
+        let keyed = hashes(random_bytes, &colors);
+        let standard = ColoredTLSH::hash_colors(random_bytes, &[0]).unwrap()[0];
+        assert_eq!(keyed.iter().map(|hash| hash.color).collect::<Vec<_>>(), markers);
+        for (i, hash) in keyed.iter().enumerate() {
+            assert!(ColoredTLSH::try_diff(hash, &standard).is_err());
+            assert_ne!(hash.tlsh.to_digest(), standard.tlsh.to_digest());
+            for other in &keyed[i + 1..] {
+                assert!(ColoredTLSH::try_diff(hash, other).is_err());
+                assert_ne!(hash.tlsh.to_digest(), other.tlsh.to_digest());
+            }
+            let parsed = ColoredTLSH::try_from_digest(&hash.to_digest()).unwrap();
+            assert_eq!(ColoredTLSH::try_diff(hash, &parsed), Ok(0));
+        }
+        // The same key gives the same hashes
+        assert_eq!(hashes(random_bytes, &colors[..1])[0].to_digest(), keyed[0].to_digest());
+
+        // Keyed colors are restored with their keys, and hashed in parallel like the others
+        let mut rng = crate::util::SplitMix64::new(71);
+        let data: Vec<u8> = (0..300_000).map(|_| (rng.next_u64() % 100) as u8).collect();
+        let mut builder = ColoredTLSHBuilder::with_keyed_colors(&colors, TLSHOptions::default());
+        builder.update(&data[..500]);
+        let blob = builder.to_checkpoint();
+        assert!(ColoredTLSHBuilder::from_checkpoint(&blob).is_err());
+        let mut restored = ColoredTLSHBuilder::from_keyed_checkpoint(&blob, &colors).unwrap();
+        restored.update_parallel(&data[500..], 2);
+        restored.finalize();
+        for (a, b) in restored.get_hashes().iter().zip(hashes(&data, &colors)) {
+            assert_eq!(a.unwrap().to_digest(), b.to_digest());
+        }
+        assert_eq!(format!("{:?}", colors[0]), format!("KeyedColor {{ marker: {}, .. }}", markers[0]));
+    }
+
+    #[test]
+    fn test_shared_marker() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let mut seen = std::collections::HashMap::new();
+        let (a, b) = (0u128..)
+            .map(|i| KeyedColor::new(&i.to_le_bytes()))
+            .find_map(|color| seen.insert(color.marker(), color.clone()).map(|other| (other, color)))
+            .unwrap();
+        assert_eq!(a.marker(), b.marker());
+        assert_ne!(a.key_id(), b.key_id());
+
+        let (hash_a, hash_b) = (hashes(random_bytes, std::slice::from_ref(&a))[0], hashes(random_bytes, std::slice::from_ref(&b))[0]);
+        assert_eq!(hash_a.key_id, Some(a.key_id()));
+        assert_eq!(ColoredTLSH::try_diff(&hash_a, &hash_b), Err(TLSHDiffError::KeyMismatch));
+        let digest = hash_a.to_digest();
+        assert_eq!(digest.len(), 80);
+        let parsed = ColoredTLSH::try_from_digest(&digest).unwrap();
+        assert_eq!(parsed.key_id, hash_a.key_id);
+        assert_eq!(ColoredTLSH::try_diff(&parsed, &hash_a), Ok(0));
+        assert_eq!(ColoredTLSH::try_diff(&parsed, &hash_b), Err(TLSHDiffError::KeyMismatch));
+        assert_eq!(ColoredTLSH::try_from_raw(&hash_a.to_raw()).unwrap().to_digest(), digest);
+
+        // A checkpoint is not restored with another key of the same marker
+        let mut builder = ColoredTLSHBuilder::with_keyed_colors(std::slice::from_ref(&a), TLSHOptions::default());
+        builder.update(random_bytes);
+        let blob = builder.to_checkpoint();
+        assert!(ColoredTLSHBuilder::from_keyed_checkpoint(&blob, &[a]).is_ok());
+        assert_eq!(ColoredTLSHBuilder::from_keyed_checkpoint(&blob, &[b]).err(), Some(CheckpointError::InvalidColor));
+    }
+
+    #[test]
+    #[should_panic(expected = "Keyed colors of the same marker")]
+    fn test_same_marker() {
+        ColoredTLSHBuilder::with_keyed_colors(&[KeyedColor::new(&[1; 16]), KeyedColor::new(&[1; 16])], TLSHOptions::default());
+    }
+}
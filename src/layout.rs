@@ -0,0 +1,198 @@
+use std::fmt::Write;
+
+use crate::{hash::TLSH, util::SplitMix64};
+
+const POWER_ITERATIONS: usize = 300;
+
+/// 2D coordinates of a hash corpus, placing similar hashes close to each other
+///
+/// Computed by landmark multidimensional scaling (LMDS): classical MDS is run on the
+/// `TLSH::diff` distances of a few landmark hashes, and every other hash is placed by its
+/// distances to the landmarks. The cost is `O(n * landmarks)` distance calculations, so a few
+/// hundred landmarks are enough to lay out 100k hashes in seconds.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{Layout, TLSH};
+/// let hashes = [
+///     TLSH::from_digest("53152333A0D13738E4B172B10F6AC6135BEF7A225664750839D69F8D8E3B6C8D56932C"),
+///     TLSH::from_digest("94052217B1A73B39E46588F54EA5C09C2CFF3F222934210EB1ACA9491F7F7C0955A792"),
+/// ];
+/// let layout = Layout::compute(&hashes, 256, 0);
+/// assert_eq!(layout.points.len(), 2);
+/// assert!(layout.to_csv().starts_with("index,x,y\n"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Layout {
+    /// Coordinates of the hashes, in the order of the input
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Layout {
+    /// Computes the layout of `hashes`
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - Hashes to lay out
+    /// * `landmarks` - Maximal number of landmarks, picked by farthest point sampling
+    /// * `seed` - Seed of the first landmark choice, the same seed gives the same layout
+    pub fn compute(hashes: &[TLSH], landmarks: usize, seed: u64) -> Self {
+        if hashes.is_empty() {
+            return Self { points: Vec::new() };
+        }
+        let picked = Self::pick_landmarks(hashes, landmarks.max(1), seed);
+        let k = picked.len();
+
+        // Squared distances among landmarks, double centered
+        let mut sq = vec![0.0f64; k * k];
+        for i in 0..k {
+            for j in 0..k {
+                let d = TLSH::diff(&hashes[picked[i]], &hashes[picked[j]]) as f64;
+                sq[i * k + j] = d * d;
+            }
+        }
+        let means: Vec<f64> = (0..k).map(|i| sq[i * k..(i + 1) * k].iter().sum::<f64>() / k as f64).collect();
+        let grand_mean = means.iter().sum::<f64>() / k as f64;
+        let mut b = vec![0.0f64; k * k];
+        for i in 0..k {
+            for j in 0..k {
+                b[i * k + j] = -0.5 * (sq[i * k + j] - means[i] - means[j] + grand_mean);
+            }
+        }
+
+        let mut rng = SplitMix64::new(seed);
+        let (l1, v1) = Self::top_eigenpair(&b, k, &mut rng);
+        for i in 0..k {
+            for j in 0..k {
+                b[i * k + j] -= l1 * v1[i] * v1[j];
+            }
+        }
+        let (l2, v2) = Self::top_eigenpair(&b, k, &mut rng);
+
+        // Pseudo-inverse of the landmark coordinates, scaled by -1/2
+        let scale = |l: f64| if l > f64::EPSILON { -0.5 / l.sqrt() } else { 0.0 };
+        let p1: Vec<f64> = v1.iter().map(|x| x * scale(l1)).collect();
+        let p2: Vec<f64> = v2.iter().map(|x| x * scale(l2)).collect();
+
+        let points = hashes
+            .iter()
+            .map(|h| {
+                let (mut x, mut y) = (0.0, 0.0);
+                for (i, landmark) in picked.iter().enumerate() {
+                    let d = TLSH::diff(h, &hashes[*landmark]) as f64;
+                    let delta = d * d - means[i];
+                    x += p1[i] * delta;
+                    y += p2[i] * delta;
+                }
+                (x, y)
+            })
+            .collect();
+        Self { points }
+    }
+
+    fn pick_landmarks(hashes: &[TLSH], count: usize, seed: u64) -> Vec<usize> {
+        let mut rng = SplitMix64::new(seed);
+        let mut picked = vec![rng.below(hashes.len())];
+        let mut nearest: Vec<i32> = hashes.iter().map(|h| TLSH::diff(h, &hashes[picked[0]])).collect();
+        while picked.len() < count {
+            let (far, &d) = nearest.iter().enumerate().max_by_key(|(_, d)| **d).unwrap();
+            if d == 0 {
+                // The rest are duplicates of landmarks
+                break;
+            }
+            picked.push(far);
+            for (n, h) in nearest.iter_mut().zip(hashes) {
+                *n = (*n).min(TLSH::diff(h, &hashes[far]));
+            }
+        }
+        picked
+    }
+
+    /// Largest eigenvalue and its unit eigenvector of a symmetric `k * k` matrix by power iteration
+    fn top_eigenpair(m: &[f64], k: usize, rng: &mut SplitMix64) -> (f64, Vec<f64>) {
+        let mut v: Vec<f64> = (0..k).map(|_| (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 - 0.5).collect();
+        let mut eigenvalue = 0.0;
+        for _ in 0..POWER_ITERATIONS {
+            let mut w: Vec<f64> = (0..k).map(|i| m[i * k..(i + 1) * k].iter().zip(&v).map(|(a, b)| a * b).sum()).collect();
+            let norm = w.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm <= f64::EPSILON {
+                return (0.0, vec![0.0; k]);
+            }
+            w.iter_mut().for_each(|x| *x /= norm);
+            // Rayleigh quotient, negative for a dominant negative eigenvalue
+            eigenvalue = (0..k)
+                .map(|i| w[i] * m[i * k..(i + 1) * k].iter().zip(&w).map(|(a, b)| a * b).sum::<f64>())
+                .sum();
+            v = w;
+        }
+        (eigenvalue, v)
+    }
+
+    /// Exports the coordinates as CSV with an `index,x,y` header
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("index,x,y\n");
+        for (i, (x, y)) in self.points.iter().enumerate() {
+            writeln!(csv, "{i},{x},{y}").unwrap();
+        }
+        csv
+    }
+
+    /// Exports the coordinates as a JSON array of `{"index": i, "x": x, "y": y}` objects
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, (x, y)) in self.points.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{{\"index\":{i},\"x\":{x},\"y\":{y}}}").unwrap();
+        }
+        json.push(']');
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clusters() {
+        let mut rng = SplitMix64::new(3);
+        let mut hashes = Vec::new();
+        for _ in 0..3 {
+            let base = rng.bytes(4096);
+            for _ in 0..10 {
+                let mut variant = base.clone();
+                for _ in 0..50 {
+                    let i = rng.below(variant.len());
+                    variant[i] = rng.next_u64() as u8;
+                }
+                hashes.push(TLSH::hash(&variant).unwrap());
+            }
+        }
+        let layout = Layout::compute(&hashes, 8, 0);
+        let dist = |a: usize, b: usize| {
+            let (ax, ay) = layout.points[a];
+            let (bx, by) = layout.points[b];
+            ((ax - bx).powi(2) + (ay - by).powi(2)).sqrt()
+        };
+        for cluster in 0..3 {
+            for other in 0..3 {
+                if cluster != other {
+                    let within = (0..10).map(|i| dist(cluster * 10, cluster * 10 + i)).fold(0.0, f64::max);
+                    let across = (0..10).map(|i| dist(cluster * 10, other * 10 + i)).fold(f64::MAX, f64::min);
+                    assert!(within < across);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let layout = Layout { points: vec![(1.0, -2.5), (0.0, 3.0)] };
+        assert_eq!(layout.to_csv(), "index,x,y\n0,1,-2.5\n1,0,3\n");
+        assert_eq!(layout.to_json(), r#"[{"index":0,"x":1,"y":-2.5},{"index":1,"x":0,"y":3}]"#);
+        assert!(Layout::compute(&[], 10, 0).points.is_empty());
+    }
+}
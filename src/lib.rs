@@ -1,14 +1,40 @@
+mod aggregate;
 mod builder;
+mod cdc;
 mod diff;
 mod digest;
+mod embed;
+mod extended;
 mod hash;
+mod io;
+mod keyed;
+mod layout;
+mod prefix;
+mod report;
+mod sample;
+mod segment;
+mod stats;
+mod synth;
 mod util;
 mod vec;
 pub use vec::tlsh_diff_mode;
 
 pub use crate::{
-    builder::{ColoredTLSHBuilder, TLSHBuilder, TLSHError},
+    builder::{CheckpointError, ColoredTLSHBuilder, InterimHash, TLSHBuilder, TLSHError, TLSHOptions},
     hash::TLSH, hash::ColoredTLSH, digest::TLSHDigestError,
+    io::{hash_file, hash_file_mmap, hash_reader},
+    aggregate::{hash_directory, AggregateOptions, TLSHAggregator, Weighting},
+    cdc::{ChunkComparison, ChunkOptions, ChunkedTLSH},
+    embed::{hamming_distance, l1_distance, EMBEDDING_DIM, HAMMING_CODE_SIZE},
+    extended::{ExtendedTLSH, EXTENDED_HASH_SIZE},
+    keyed::{KeyedColor, MIN_KEYED_MARKER},
+    layout::Layout,
+    prefix::{geometric_points, PrefixMatch, PrefixMatcher},
+    report::TLSHReport,
+    sample::{hash_file_sampled, hash_sampled, SampleOptions, SampledHashes},
+    segment::{hash_file_split, hash_split, Segment, SegmentComparison, SegmentMatch, SegmentOptions, SegmentedTLSH},
+    synth::HashGenerator,
+    stats::{DistanceHistogram, NullModel, LVALUE_BAND_WIDTH},
 };
 
 #[cfg(test)]
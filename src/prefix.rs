@@ -0,0 +1,128 @@
+use crate::{hash::TLSH, util::MAX_DATA_LENGTH};
+
+/// Interim points from `first`, each `factor` times the previous one, up to the longest data
+/// TLSH can hash
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::geometric_points;
+/// assert_eq!(geometric_points(4096, 4)[..4], [4096, 16384, 65536, 262144]);
+/// ```
+///
+/// # Panics
+///
+/// The function panics if `first` is 0 or `factor` is less than 2.
+pub fn geometric_points(first: u64, factor: u64) -> Vec<u64> {
+    assert!(first > 0 && factor >= 2, "Invalid geometric points");
+    std::iter::successors(Some(first), |point| point.checked_mul(factor))
+        .take_while(|point| *point <= MAX_DATA_LENGTH)
+        .collect()
+}
+
+/// A hash of the database found by `PrefixMatcher::find`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PrefixMatch {
+    /// Index of the hash in the database
+    pub index: usize,
+    /// Distance of the hashes, without the length difference
+    pub distance: i32,
+}
+
+/// Compares interim hashes of a prefix of data with hashes of whole data
+///
+/// The lvalue of a prefix is lower than that of the whole data, so the matcher compares by
+/// `TLSH::diff_without_length`. The distances shrink as the prefix grows, see
+/// `ColoredTLSHBuilder::set_interim_points`. Short prefixes say little, set the threshold by the
+/// length of the prefix.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{geometric_points, PrefixMatcher, TLSHBuilder, TLSH};
+/// let random = include_bytes!("../test/data/random.txt");
+/// let file: Vec<u8> = random.repeat(1024).iter().map(|b| b"etaoin shrdlu\n"[*b as usize % 14]).collect();
+/// let matcher = PrefixMatcher::new(vec![TLSH::hash(&file).unwrap()]);
+///
+/// let mut builder = TLSHBuilder::new();
+/// builder.set_interim_points(&geometric_points(4096, 4));
+/// for chunk in file.chunks(1000) {
+///     builder.update(chunk);
+///     for (len, hash) in builder.take_interim_hashes() {
+///         let matches = matcher.find(&hash.unwrap(), 100);
+///         if len >= 65536 {
+///             assert_eq!(matches[0].index, 0);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PrefixMatcher {
+    hashes: Vec<TLSH>,
+}
+
+impl PrefixMatcher {
+    pub fn new(hashes: Vec<TLSH>) -> Self {
+        Self { hashes }
+    }
+
+    /// Add a hash to the database, returning its index
+    pub fn add(&mut self, hash: TLSH) -> usize {
+        self.hashes.push(hash);
+        self.hashes.len() - 1
+    }
+
+    /// The hashes of the database
+    pub fn hashes(&self) -> &[TLSH] {
+        &self.hashes
+    }
+
+    /// Find the hashes of the database within `max_distance` of `interim`, closest first
+    pub fn find(&self, interim: &TLSH, max_distance: i32) -> Vec<PrefixMatch> {
+        let mut matches: Vec<PrefixMatch> = self
+            .hashes
+            .iter()
+            .enumerate()
+            .map(|(index, hash)| PrefixMatch { index, distance: TLSH::diff_without_length(interim, hash) })
+            .filter(|m| m.distance <= max_distance)
+            .collect();
+        matches.sort_unstable_by_key(|m| (m.distance, m.index));
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::SplitMix64, TLSHBuilder};
+
+    /// Random data over an alphabet of random weights, different for each seed
+    fn source(seed: u64, len: usize) -> Vec<u8> {
+        SplitMix64::new(seed).weighted(len)
+    }
+
+    #[test]
+    fn test_prefix_matching() {
+        const LEN: usize = 1 << 20;
+        let files: Vec<Vec<u8>> = (0..20).map(|seed| source(seed, LEN)).collect();
+        let matcher = PrefixMatcher::new(files.iter().map(|file| TLSH::hash(file).unwrap()).collect());
+
+        for (index, file) in files.iter().enumerate().step_by(4) {
+            let mut builder = TLSHBuilder::new();
+            builder.set_interim_points(&geometric_points(4096, 4));
+            builder.update(file);
+            let mut distances = Vec::new();
+            for (len, hash) in builder.take_interim_hashes() {
+                let hash = hash.unwrap();
+                let matches = matcher.find(&hash, 1000);
+                assert_eq!(matches.len(), files.len());
+                assert_eq!(matches[0].index, index, "prefix of {len} bytes");
+                assert!(matches[0].distance < TLSH::diff(&hash, &matcher.hashes()[index]) || len == LEN as u64);
+                distances.push(matches[0].distance);
+            }
+            assert_eq!(distances.len(), 5);
+            assert!(distances[4] < distances[0], "{distances:?}");
+        }
+        assert!(matcher.find(&TLSH::hash(&source(99, LEN)).unwrap(), 20).is_empty());
+    }
+}
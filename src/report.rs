@@ -0,0 +1,150 @@
+use crate::{
+    builder::{ColoredTLSHBuilder, TLSHError},
+    hash::{ColoredTLSH, EFF_BUCKETS},
+};
+
+/// Number of buckets per code in an ideally balanced hash
+const BALANCED_CODE_COUNT: usize = EFF_BUCKETS / 4;
+
+/// Details of a hash calculation, to judge how far the hash can be trusted
+///
+/// Get it from `ColoredTLSHBuilder::get_reports` after `finalize`. The details are available
+/// even when the hash could not be calculated.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::TLSHBuilder;
+/// let mut builder = TLSHBuilder::new();
+/// builder.update(include_bytes!("../test/data/random.txt"));
+/// builder.finalize();
+/// let report = builder.get_report();
+/// assert!(report.hash.is_ok());
+/// assert!(report.nonzero > 64);
+/// assert!(!report.forced);
+/// ```
+#[derive(Clone, Debug)]
+pub struct TLSHReport {
+    /// The hash, or the reason it could not be calculated
+    pub hash: Result<ColoredTLSH, TLSHError>,
+    /// Raw bucket counts, the hash uses the first 128
+    pub buckets: [u64; 256],
+    /// The quartiles of the first 128 bucket counts, the bounds of the bucket codes
+    pub quartiles: [u64; 3],
+    /// Number of the first 128 buckets with a non-zero count
+    ///
+    /// Hashes need more than 64 unless forced, see `TLSHOptions::force`.
+    pub nonzero: usize,
+    /// Shannon entropy of the byte values of the data, from 0 to 8 bits
+    pub byte_entropy: f64,
+    /// Confidence in the hash, from 0 to 1
+    ///
+    /// How evenly the four codes are used in the hash body, times the byte entropy divided by 8,
+    /// as unrelated data of few byte values gets close hashes.
+    pub quality: f64,
+    /// Whether the hash was forced from data of insufficient variety
+    pub forced: bool,
+}
+
+impl TLSHReport {
+    pub(crate) fn new(
+        hash: Result<ColoredTLSH, TLSHError>,
+        buckets: [u64; 256],
+        forced: bool,
+        byte_counts: &[u64; 256],
+    ) -> Self {
+        let (q1, q2, q3) = ColoredTLSHBuilder::find_quartile(&buckets);
+        let effective = &buckets[..EFF_BUCKETS];
+        let nonzero = effective.iter().filter(|count| **count > 0).count();
+
+        let total: u64 = byte_counts.iter().sum();
+        let byte_entropy = byte_counts
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f64 / total as f64;
+                -p * p.log2()
+            })
+            .sum::<f64>();
+
+        let mut code_counts = [0usize; 4];
+        for count in effective {
+            let code = match *count {
+                k if q3 < k => 3,
+                k if q2 < k => 2,
+                k if q1 < k => 1,
+                _ => 0,
+            };
+            code_counts[code] += 1;
+        }
+        let imbalance: usize = code_counts.iter().map(|n| n.abs_diff(BALANCED_CODE_COUNT)).sum();
+        let max_imbalance = 2 * (EFF_BUCKETS - BALANCED_CODE_COUNT);
+        let balance = 1.0 - imbalance as f64 / max_imbalance as f64;
+        let quality = balance * byte_entropy / 8.0;
+
+        Self {
+            hash,
+            buckets,
+            quartiles: [q1, q2, q3],
+            nonzero,
+            byte_entropy,
+            quality,
+            forced,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::SplitMix64, TLSHBuilder, TLSHOptions};
+
+    fn report(data: &[u8], options: TLSHOptions) -> TLSHReport {
+        let mut builder = TLSHBuilder::with_options(options);
+        builder.update(data);
+        builder.finalize();
+        builder.get_report()
+    }
+
+    #[test]
+    fn test_reports() {
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let random = report(random_bytes, TLSHOptions::default());
+        assert_eq!(
+            random.hash.unwrap().tlsh.to_digest(),
+            "9411A5B6ECD2709D603191F2EA5016E0E51DA2AF05374F66BD80DB25B1604DB9C89110"
+        );
+        assert_eq!(random.buckets.iter().sum::<u64>(), 6 * (1024 - 4));
+        assert!(random.quartiles[0] <= random.quartiles[1] && random.quartiles[1] <= random.quartiles[2]);
+        assert!(random.nonzero > 120);
+        let entropy = random.byte_entropy;
+        assert!(entropy > 7.5 && entropy <= 8.0, "{entropy}");
+        assert!(random.quality > 0.85, "{}", random.quality);
+        assert!(!random.forced);
+
+        // Random data over 8 byte values hashes fine, but its matches are weak evidence
+        let mut rng = SplitMix64::new(41);
+        let alphabet: Vec<u8> = (0..4000).map(|_| b"ACGTacgt"[rng.below(8)]).collect();
+        let low = report(&alphabet, TLSHOptions::default());
+        assert!(low.hash.is_ok());
+        assert!((low.byte_entropy - 3.0).abs() < 0.01);
+        assert!(low.quality < 0.4, "{}", low.quality);
+
+        // Uses 50 of the 128 buckets
+        let periodic: Vec<u8> = (0..1000).map(|i| (i % 20) as u8 * 7).collect();
+        let failed = report(&periodic, TLSHOptions::default());
+        let Err(TLSHError::Variety { nonzero, quartiles, .. }) = failed.hash else {
+            panic!("{:?}", failed.hash)
+        };
+        assert_eq!((failed.nonzero, failed.quartiles), (nonzero, quartiles));
+        assert!((failed.byte_entropy - 20f64.log2()).abs() < 1e-9);
+        assert!(failed.quality < random.quality);
+
+        let forced = report(&periodic, TLSHOptions { force: true, ..TLSHOptions::default() });
+        assert!(forced.hash.is_ok() && forced.forced);
+        assert_eq!(forced.buckets, failed.buckets);
+
+        let empty = report(&[], TLSHOptions::default());
+        assert_eq!((empty.nonzero, empty.byte_entropy, empty.quality), (0, 0.0, 0.0));
+    }
+}
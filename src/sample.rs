@@ -0,0 +1,230 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    builder::{ColoredTLSHBuilder, TLSHError},
+    hash::ColoredTLSH,
+};
+
+/// Options of sampled hashing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SampleOptions {
+    /// Length of a sampled block in bytes
+    pub block_size: usize,
+    /// Number of sampled blocks, at least 2 for the head and the tail
+    pub blocks: usize,
+}
+
+impl Default for SampleOptions {
+    /// 64 blocks of 64 KiB, 4 MiB of the data
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 16,
+            blocks: 64,
+        }
+    }
+}
+
+/// Hashes calculated by `hash_sampled`
+#[derive(Clone, Debug)]
+pub struct SampledHashes {
+    /// The hashes of the colors, in order
+    pub hashes: Vec<Result<ColoredTLSH, TLSHError>>,
+    /// Whether the hashes were calculated from samples, `false` if the data was hashed as a whole
+    pub sampled: bool,
+    /// Length of the hashed samples
+    pub sampled_len: u64,
+    /// Length of the data
+    pub data_len: u64,
+}
+
+/// Calculate approximate TLSH hashes of data from samples of it
+///
+/// Only the first, the last and evenly spaced blocks between them are hashed, with the lvalue
+/// of the whole data. Data not longer than the samples together is hashed as a whole.
+///
+/// Sampled distances follow the full ones roughly. On 30 pairs of 64 MiB images of 1 MiB regions
+/// of 8 kinds, with 0-100% of the regions replaced, the full distances were 0-78 and the sampled
+/// ones about `1.00 * full + 3.4`, 4.0 off on average. The sampled hash of an image was 9-21 from
+/// its full hash. Of random data it was 170 away, about as far as unrelated data, so confirm
+/// candidates with full hashes. See the ignored test `measure_sampled_distances`.
+///
+/// # Panics
+///
+/// The function panics if the block size is 0 or there are less than 2 blocks.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{hash_sampled, SampleOptions};
+/// let data = include_bytes!("../test/data/random.txt").repeat(1024);
+/// let options = SampleOptions { block_size: 4096, blocks: 16 };
+/// let sampled = hash_sampled(&data, &[0], options);
+/// assert!(sampled.sampled);
+/// assert_eq!((sampled.sampled_len, sampled.data_len), (65536, 1 << 20));
+/// assert!(sampled.hashes[0].is_ok());
+/// ```
+pub fn hash_sampled(data: &[u8], colors: &[u8], options: SampleOptions) -> SampledHashes {
+    assert!(options.block_size > 0 && options.blocks >= 2, "Invalid sample options");
+    let mut builder = ColoredTLSHBuilder::new(colors);
+    let sampled = is_sampled(data.len() as u64, options);
+    if sampled {
+        for offset in sample_offsets(data.len() as u64, options) {
+            let offset = offset as usize;
+            builder.update(&data[offset..offset + options.block_size]);
+        }
+    } else {
+        builder.update(data);
+    }
+    finish(builder, options, sampled, data.len() as u64)
+}
+
+/// Calculate approximate TLSH hashes of a file from samples of it
+///
+/// Only the sampled blocks of regular files are read, other files are read as a whole. See
+/// `hash_sampled`.
+pub fn hash_file_sampled(path: impl AsRef<Path>, colors: &[u8], options: SampleOptions) -> io::Result<SampledHashes> {
+    assert!(options.block_size > 0 && options.blocks >= 2, "Invalid sample options");
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || !is_sampled(metadata.len(), options) {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        return Ok(hash_sampled(&data, colors, options));
+    }
+    let mut builder = ColoredTLSHBuilder::new(colors);
+    let mut block = vec![0; options.block_size];
+    for offset in sample_offsets(metadata.len(), options) {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut block)?;
+        builder.update(&block);
+    }
+    Ok(finish(builder, options, true, metadata.len()))
+}
+
+fn is_sampled(len: u64, options: SampleOptions) -> bool {
+    len > (options.block_size as u64).saturating_mul(options.blocks as u64)
+}
+
+/// Offsets of the sampled blocks of data of `len` bytes: the head, the tail and blocks evenly
+/// between them
+fn sample_offsets(len: u64, options: SampleOptions) -> impl Iterator<Item = u64> {
+    let last = len - options.block_size as u64;
+    (0..options.blocks).map(move |i| (last as u128 * i as u128 / (options.blocks - 1) as u128) as u64)
+}
+
+fn finish(mut builder: ColoredTLSHBuilder, options: SampleOptions, sampled: bool, data_len: u64) -> SampledHashes {
+    builder.finalize_with_length(data_len);
+    SampledHashes {
+        hashes: builder.get_hashes(),
+        sampled,
+        sampled_len: if sampled { (options.block_size * options.blocks) as u64 } else { data_len },
+        data_len,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::SplitMix64, TLSH};
+
+    #[test]
+    fn test_sampled() {
+        let mut rng = SplitMix64::new(61);
+        // Few symbols, so the bucket counts differ by more than the noise of sampling
+        let data: Vec<u8> = (0..1 << 20).map(|_| b"tlsh\n"[rng.below(5)]).collect();
+        let options = SampleOptions { block_size: 1000, blocks: 10 };
+
+        let sampled = hash_sampled(&data, &[0, 2], options);
+        assert!(sampled.sampled);
+        assert_eq!((sampled.sampled_len, sampled.data_len), (10000, 1 << 20));
+        let hash = sampled.hashes[0].unwrap().tlsh;
+        let full = TLSH::hash(&data).unwrap();
+        assert_eq!(hash.lvalue, full.lvalue);
+        assert!(TLSH::diff(&hash, &full) < 100, "{}", TLSH::diff(&hash, &full));
+        assert_eq!(sampled.hashes[1].unwrap().color, 2);
+
+        // The samples are the head, the tail and blocks evenly between them
+        let mut samples = Vec::new();
+        for i in 0..10 {
+            let offset = ((1 << 20) - 1000) * i / 9;
+            samples.extend_from_slice(&data[offset..offset + 1000]);
+        }
+        let mut builder = ColoredTLSHBuilder::new(&[0]);
+        builder.update(&samples);
+        builder.finalize_with_length(1 << 20);
+        assert_eq!(builder.get_hashes()[0].unwrap().tlsh.to_digest(), hash.to_digest());
+
+        let short = hash_sampled(&data[..10000], &[0], options);
+        assert!(!short.sampled);
+        assert_eq!(short.hashes[0].unwrap().tlsh.to_digest(), TLSH::hash(&data[..10000]).unwrap().to_digest());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/data/random.txt");
+        let random_bytes = include_bytes!("../test/data/random.txt");
+        let options = SampleOptions { block_size: 100, blocks: 4 };
+        let from_file = hash_file_sampled(path, &[0], options).unwrap();
+        assert!(from_file.sampled);
+        let from_memory = hash_sampled(random_bytes, &[0], options);
+        assert_eq!(from_file.hashes[0].unwrap().to_digest(), from_memory.hashes[0].unwrap().to_digest());
+    }
+
+    /// Prints the comparison of sampled and full distances quoted by `hash_sampled`, run by
+    /// `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore = "measurement"]
+    fn measure_sampled_distances() {
+        const REGION: usize = 1 << 20;
+        const REGIONS: usize = 64;
+        const PAIRS: usize = 30;
+        let mut rng = SplitMix64::new(49);
+        let kinds: Vec<Vec<u8>> = (0..8).map(|_| rng.weighted(2 * REGION)).collect();
+        let region = |rng: &mut SplitMix64| {
+            let offset = rng.below(REGION);
+            kinds[rng.below(kinds.len())][offset..offset + REGION].to_vec()
+        };
+        let sampled = |data: &[u8]| hash_sampled(data, &[0], SampleOptions::default()).hashes[0].unwrap().tlsh;
+
+        let (mut full, mut approx, mut own) = (Vec::new(), Vec::new(), Vec::new());
+        for pair in 0..PAIRS {
+            let regions: Vec<Vec<u8>> = (0..REGIONS).map(|_| region(&mut rng)).collect();
+            let mut changed = regions.clone();
+            let mut order: Vec<usize> = (0..REGIONS).collect();
+            for i in (1..REGIONS).rev() {
+                order.swap(i, rng.below(i + 1));
+            }
+            for i in &order[..REGIONS * pair / (PAIRS - 1)] {
+                changed[*i] = region(&mut rng);
+            }
+            let (a, b) = (regions.concat(), changed.concat());
+            let (full_a, sampled_a) = (TLSH::hash(&a).unwrap(), sampled(&a));
+            full.push(TLSH::diff(&full_a, &TLSH::hash(&b).unwrap()) as f64);
+            approx.push(TLSH::diff(&sampled_a, &sampled(&b)) as f64);
+            own.push(TLSH::diff(&sampled_a, &full_a));
+        }
+
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let (mx, my) = (mean(&full), mean(&approx));
+        let covariance: f64 = full.iter().zip(&approx).map(|(x, y)| (x - mx) * (y - my)).sum();
+        let (vx, vy): (f64, f64) = (
+            full.iter().map(|x| (x - mx).powi(2)).sum(),
+            approx.iter().map(|y| (y - my).powi(2)).sum(),
+        );
+        let slope = covariance / vx;
+        let error = full.iter().zip(&approx).map(|(x, y)| (x - y).abs()).sum::<f64>() / PAIRS as f64;
+        let max = full.iter().cloned().fold(0.0, f64::max);
+        println!("full distances up to {max}, sampled about {slope:.2} * full + {:.1}", my - slope * mx);
+        println!("mean absolute difference {error:.1}, correlation {:.2}", covariance / (vx * vy).sqrt());
+        println!("sampled from full hash {}-{}", own.iter().min().unwrap(), own.iter().max().unwrap());
+
+        let random = [rng.bytes(REGIONS * REGION), rng.bytes(REGIONS * REGION)];
+        let full_random = TLSH::hash(&random[0]).unwrap();
+        println!(
+            "random data: sampled from full hash {}, unrelated {}",
+            TLSH::diff(&sampled(&random[0]), &full_random),
+            TLSH::diff(&TLSH::hash(&random[1]).unwrap(), &full_random)
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use std::{fs, io, path::Path};
+
+use crate::{builder::TLSHError, hash::TLSH, ColoredTLSHBuilder};
+
+/// Options of splitting data into blocks by `SegmentedTLSH`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SegmentOptions {
+    /// Length of a block in bytes
+    pub block_size: usize,
+    /// Distance of the starts of neighbouring blocks in bytes
+    ///
+    /// Equal to the block size, the blocks tile the data. A smaller step gives overlapping
+    /// blocks, so a block of any content is hashed at most `step / 2` bytes off its start. A
+    /// larger step would leave gaps between the blocks, it is rejected.
+    pub step: usize,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            step: 4096,
+        }
+    }
+}
+
+/// The hash of one block of data, of a `SegmentedTLSH` or a `ChunkedTLSH`
+#[derive(Copy, Clone, Debug)]
+pub struct Segment {
+    /// Start of the block in the data
+    pub offset: u64,
+    /// Length of the block, for `SegmentedTLSH` the block size except for data shorter than a
+    /// block
+    pub len: usize,
+    /// The hash of the block, or the reason it could not be calculated
+    pub hash: Result<TLSH, TLSHError>,
+}
+
+/// A pair of matching blocks found by `SegmentedTLSH::compare` or `ChunkedTLSH::compare`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SegmentMatch {
+    /// Offset of the block in the first data
+    pub offset_a: u64,
+    /// Offset of the block in the second data
+    pub offset_b: u64,
+    /// TLSH distance of the blocks
+    pub distance: i32,
+}
+
+/// Result of `SegmentedTLSH::compare`
+#[derive(Clone, Debug)]
+pub struct SegmentComparison {
+    /// The matching blocks, in increasing order of both offsets
+    pub matches: Vec<SegmentMatch>,
+    /// Fraction of the hashed blocks of the first data that have a match, from 0 to 1
+    pub containment: f64,
+}
+
+/// A sequence of TLSH hashes of the blocks of data
+///
+/// Finds a small payload embedded into a large file, which hardly changes the hash of the whole
+/// file. Blocks start at multiples of the step, a last block is aligned to the end of the data.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{SegmentOptions, SegmentedTLSH};
+/// let random = include_bytes!("../test/data/random.txt");
+/// let payload: Vec<u8> = (0..8).flat_map(|k| random.map(|b| b ^ k)).collect();
+/// let mut container = b"The quick brown fox jumps over the lazy dog. ".repeat(1500);
+/// container[20000..28192].copy_from_slice(&payload);
+///
+/// let payload = SegmentedTLSH::hash(&payload, SegmentOptions { block_size: 2048, step: 2048 });
+/// let container = SegmentedTLSH::hash(&container, SegmentOptions { block_size: 2048, step: 256 });
+/// let comparison = SegmentedTLSH::compare(&payload, &container, 50);
+/// assert_eq!(comparison.containment, 1.0);
+/// assert!(comparison.matches[0].offset_b.abs_diff(20000) <= 128);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SegmentedTLSH {
+    pub options: SegmentOptions,
+    pub segments: Vec<Segment>,
+}
+
+impl SegmentedTLSH {
+    /// Calculate the hashes of the blocks of data
+    ///
+    /// # Panics
+    ///
+    /// The method panics unless `0 < step <= block_size`.
+    pub fn hash(data: &[u8], options: SegmentOptions) -> Self {
+        assert!(0 < options.step && options.step <= options.block_size, "Invalid segment options");
+        let mut builder = ColoredTLSHBuilder::new(&[0]);
+        let segments = block_offsets(data.len(), options)
+            .map(|offset| {
+                let block = &data[offset..data.len().min(offset + options.block_size)];
+                Segment {
+                    offset: offset as u64,
+                    len: block.len(),
+                    hash: builder.hash_complete(block)[0].map(|hash| hash.tlsh),
+                }
+            })
+            .collect();
+        Self { options, segments }
+    }
+
+    /// Calculate the hashes of the blocks of a file
+    ///
+    /// See `SegmentedTLSH::hash`
+    pub fn hash_file(path: impl AsRef<Path>, options: SegmentOptions) -> io::Result<Self> {
+        Ok(Self::hash(&fs::read(path)?, options))
+    }
+
+    /// Find the best aligned matching blocks of two sequences
+    ///
+    /// Blocks match if the distance of their hashes is at most `max_distance`. Of the matching
+    /// pairs, the longest chain in which both offsets increase is chosen, of equally long ones
+    /// the chain of smaller distances. The containment tells how much of `a` is found in `b`.
+    ///
+    /// To find a payload in a larger file, pass the payload as `a` and hash the file with a step
+    /// of a fraction of the block size.
+    pub fn compare(a: &Self, b: &Self, max_distance: i32) -> SegmentComparison {
+        let mut pairs = Vec::new();
+        for (i, sa) in a.segments.iter().enumerate() {
+            let Ok(ha) = &sa.hash else { continue };
+            for (j, sb) in b.segments.iter().enumerate() {
+                let Ok(hb) = &sb.hash else { continue };
+                let distance = TLSH::diff(ha, hb);
+                if distance <= max_distance {
+                    pairs.push((i, j, distance));
+                }
+            }
+        }
+
+        // Heaviest chain increasing in both indices, a pair weighing more the closer it is. A
+        // tree of prefix maxima over the indices of `b` gives the best chain ending before `j`.
+        let weight = |distance: i32| (max_distance - distance + 1) as u64;
+        let length_weight = pairs.len() as u64 * (max_distance as u64 + 1) + 1;
+        let mut tree = PrefixMax::new(b.segments.len());
+        let mut best: Vec<(u64, Option<usize>)> = Vec::with_capacity(pairs.len());
+        let mut group_start = 0;
+        while group_start < pairs.len() {
+            let i = pairs[group_start].0;
+            let group_end = group_start + pairs[group_start..].iter().take_while(|p| p.0 == i).count();
+            // Query the whole group before updating, so a chain uses one block of `a` at most once
+            for &(_, j, distance) in &pairs[group_start..group_end] {
+                let (score, previous) = tree.query(j);
+                best.push((score + length_weight + weight(distance), previous));
+            }
+            for (k, &(_, j, _)) in pairs.iter().enumerate().take(group_end).skip(group_start) {
+                tree.update(j, (best[k].0, Some(k)));
+            }
+            group_start = group_end;
+        }
+
+        let mut matches = Vec::new();
+        let mut current = best.iter().enumerate().max_by_key(|(_, b)| b.0).map(|(k, _)| k);
+        while let Some(k) = current {
+            let (i, j, distance) = pairs[k];
+            matches.push(SegmentMatch {
+                offset_a: a.segments[i].offset,
+                offset_b: b.segments[j].offset,
+                distance,
+            });
+            current = best[k].1;
+        }
+        matches.reverse();
+
+        let hashed = a.segments.iter().filter(|s| s.hash.is_ok()).count();
+        let containment = if hashed == 0 { 0.0 } else { matches.len() as f64 / hashed as f64 };
+        SegmentComparison { matches, containment }
+    }
+}
+
+/// Calculate the TLSH hashes of the segments of data between split points
+///
+/// Like the `-split` option of the reference `tlsh` tool. Points beyond the end of the data are
+/// taken as the end, and every segment has an entry, with the error of its hash if it has none.
+///
+/// # Panics
+///
+/// The function panics if the split points are not in increasing order.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{hash_split, TLSHError, TLSH};
+/// let data = include_bytes!("../test/data/random.txt").repeat(3);
+/// let segments = hash_split(&data, &[1000, 1020]);
+/// assert_eq!(segments.len(), 3);
+/// assert_eq!(segments[0].hash.unwrap().to_digest(), TLSH::hash(&data[..1000]).unwrap().to_digest());
+/// assert!(matches!(segments[1].hash, Err(TLSHError::Length { .. })));
+/// assert_eq!((segments[2].offset, segments[2].len), (1020, 2052));
+/// ```
+pub fn hash_split(data: &[u8], split_points: &[u64]) -> Vec<Segment> {
+    assert!(split_points.windows(2).all(|w| w[0] < w[1]), "Split points must increase");
+    let len = data.len() as u64;
+    let bounds: Vec<usize> = std::iter::once(0)
+        .chain(split_points.iter().map(|point| (*point).min(len)))
+        .chain(std::iter::once(len))
+        .map(|bound| bound as usize)
+        .collect();
+    let mut builder = ColoredTLSHBuilder::new(&[0]);
+    bounds
+        .windows(2)
+        .map(|w| Segment {
+            offset: w[0] as u64,
+            len: w[1] - w[0],
+            hash: builder.hash_complete(&data[w[0]..w[1]])[0].map(|hash| hash.tlsh),
+        })
+        .collect()
+}
+
+/// Calculate the TLSH hashes of the segments of a file between split points
+///
+/// The file is read into memory. See `hash_split`.
+pub fn hash_file_split(path: impl AsRef<Path>, split_points: &[u64]) -> io::Result<Vec<Segment>> {
+    Ok(hash_split(&fs::read(path)?, split_points))
+}
+
+/// Offsets of the blocks of data of `len` bytes
+// `usize::is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn block_offsets(len: usize, options: SegmentOptions) -> impl Iterator<Item = usize> {
+    let last = len.saturating_sub(options.block_size);
+    let aligned = (0..=last).step_by(options.step);
+    let tail = (last % options.step != 0).then_some(last);
+    aligned.chain(tail)
+}
+
+/// Fenwick tree of the maximum over prefixes
+struct PrefixMax {
+    tree: Vec<(u64, Option<usize>)>,
+}
+
+impl PrefixMax {
+    fn new(len: usize) -> Self {
+        Self {
+            tree: vec![(0, None); len + 1],
+        }
+    }
+
+    /// The maximum over the positions before `pos`
+    fn query(&self, pos: usize) -> (u64, Option<usize>) {
+        let mut result = (0, None);
+        let mut i = pos;
+        while i > 0 {
+            if self.tree[i].0 > result.0 {
+                result = self.tree[i];
+            }
+            i &= i - 1;
+        }
+        result
+    }
+
+    fn update(&mut self, pos: usize, value: (u64, Option<usize>)) {
+        let mut i = pos + 1;
+        while i < self.tree.len() {
+            if value.0 > self.tree[i].0 {
+                self.tree[i] = value;
+            }
+            i += i & i.wrapping_neg();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::SplitMix64;
+
+    #[test]
+    fn test_blocks() {
+        let offsets = |len, block_size, step| block_offsets(len, SegmentOptions { block_size, step }).collect::<Vec<_>>();
+        assert_eq!(offsets(5000, 2048, 1024), [0, 1024, 2048, 2952]);
+        assert_eq!(offsets(4096, 2048, 1024), [0, 1024, 2048]);
+        assert_eq!(offsets(4096, 2048, 2048), [0, 2048]);
+        assert_eq!(offsets(1000, 2048, 2048), [0]);
+        assert_eq!(offsets(0, 2048, 2048), [0]);
+
+        let data = include_bytes!("../test/data/random.txt");
+        let segmented = SegmentedTLSH::hash(data, SegmentOptions { block_size: 256, step: 200 });
+        assert_eq!(segmented.segments.len(), 5);
+        let last = segmented.segments[4];
+        assert_eq!((last.offset, last.len), (768, 256));
+        assert_eq!(last.hash.unwrap().to_digest(), TLSH::hash(&data[768..]).unwrap().to_digest());
+
+        let whole = SegmentedTLSH::hash(data, SegmentOptions::default());
+        assert_eq!(whole.segments.len(), 1);
+        assert_eq!(whole.segments[0].hash.unwrap().to_digest(), TLSH::hash(data).unwrap().to_digest());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/data/random.txt");
+        let from_file = SegmentedTLSH::hash_file(path, SegmentOptions { block_size: 256, step: 200 }).unwrap();
+        assert_eq!(from_file.segments.len(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid segment options")]
+    fn test_step_beyond_block() {
+        SegmentedTLSH::hash(&[0; 5000], SegmentOptions { block_size: 1024, step: 2048 });
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut rng = SplitMix64::new(31);
+        let payload = rng.bytes(20000);
+        let mut container = rng.bytes(150000);
+        // The payload in two pieces, in order but apart
+        container[30100..40100].copy_from_slice(&payload[..10000]);
+        container[70033..80033].copy_from_slice(&payload[10000..]);
+
+        let a = SegmentedTLSH::hash(&payload, SegmentOptions { block_size: 2000, step: 2000 });
+        let b = SegmentedTLSH::hash(&container, SegmentOptions { block_size: 2000, step: 100 });
+        let comparison = SegmentedTLSH::compare(&a, &b, 100);
+        assert_eq!(comparison.containment, 1.0);
+        assert_eq!(comparison.matches.len(), 10);
+        for m in &comparison.matches {
+            let start = if m.offset_a < 10000 { 30100 } else { 70033 - 10000 };
+            assert!((m.offset_a + start).abs_diff(m.offset_b) < 100, "{m:?}");
+        }
+        assert!(comparison.matches.windows(2).all(|w| w[0].offset_a < w[1].offset_a && w[0].offset_b < w[1].offset_b));
+
+        let unrelated = SegmentedTLSH::hash(&rng.bytes(20000), SegmentOptions { block_size: 2000, step: 100 });
+        let comparison = SegmentedTLSH::compare(&a, &unrelated, 100);
+        assert!(comparison.matches.is_empty());
+        assert_eq!(comparison.containment, 0.0);
+
+        let empty = SegmentedTLSH::hash(&[], SegmentOptions::default());
+        assert_eq!(SegmentedTLSH::compare(&empty, &b, 50).containment, 0.0);
+    }
+
+    #[test]
+    fn test_split() {
+        let data = include_bytes!("../test/data/random.txt");
+        let segments = hash_split(data, &[300, 700, 720, 5000]);
+        let bounds: Vec<_> = segments.iter().map(|s| (s.offset, s.len)).collect();
+        assert_eq!(bounds, [(0, 300), (300, 400), (700, 20), (720, 304), (1024, 0)]);
+        for segment in &segments {
+            let bytes = &data[segment.offset as usize..segment.offset as usize + segment.len];
+            match (segment.hash, TLSH::hash(bytes)) {
+                (Ok(a), Ok(b)) => assert_eq!(a.to_digest(), b.to_digest()),
+                (Err(a), Err(b)) => assert_eq!(a, b),
+                (a, b) => panic!("{a:?} != {b:?}"),
+            }
+        }
+        assert!(matches!(segments[2].hash, Err(TLSHError::Length { data_len: 20, .. })));
+
+        let whole = hash_split(data, &[]);
+        assert_eq!(whole.len(), 1);
+        assert_eq!(whole[0].hash.unwrap().to_digest(), TLSH::hash(data).unwrap().to_digest());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/data/random.txt");
+        let from_file = hash_file_split(path, &[300, 700, 720, 5000]).unwrap();
+        assert_eq!(from_file.len(), segments.len());
+        assert_eq!(from_file[1].hash.unwrap().to_digest(), segments[1].hash.unwrap().to_digest());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{hash::TLSH, util::SplitMix64};
+
+/// Width of the lvalue bands used to stratify distance distributions
+pub const LVALUE_BAND_WIDTH: u8 = 16;
+const LVALUE_BANDS: usize = 256 / LVALUE_BAND_WIDTH as usize;
+
+/// A histogram of TLSH distances
+#[derive(Clone, Debug, Default)]
+pub struct DistanceHistogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl DistanceHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a distance
+    pub fn add(&mut self, distance: i32) {
+        let d = distance.max(0) as usize;
+        if d >= self.counts.len() {
+            self.counts.resize(d + 1, 0);
+        }
+        self.counts[d] += 1;
+        self.total += 1;
+    }
+
+    /// Number of recorded distances
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of occurences of each distance, indexed by the distance
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Number of recorded distances less than or equal to `distance`
+    pub fn count_at_most(&self, distance: i32) -> u64 {
+        if distance < 0 {
+            return 0;
+        }
+        let end = (distance as usize + 1).min(self.counts.len());
+        self.counts[..end].iter().sum()
+    }
+
+    /// Empirical p-value of a distance: the estimated probability that an unrelated pair
+    /// is at most `distance` apart
+    ///
+    /// Uses the `(k + 1) / (n + 1)` estimator, so it never reports zero for a finite sample.
+    /// Returns `None` for an empty histogram.
+    pub fn p_value(&self, distance: i32) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        Some((self.count_at_most(distance) + 1) as f64 / (self.total + 1) as f64)
+    }
+
+    /// Mean of the recorded distances
+    pub fn mean(&self) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let sum: u64 = self.counts.iter().enumerate().map(|(d, c)| d as u64 * c).sum();
+        Some(sum as f64 / self.total as f64)
+    }
+
+    /// Smallest distance `d` for which at least the `q` fraction of the recorded distances are `<= d`
+    pub fn quantile(&self, q: f64) -> Option<i32> {
+        if self.total == 0 {
+            return None;
+        }
+        let target = ((q.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (d, c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= target {
+                return Some(d as i32);
+            }
+        }
+        None
+    }
+}
+
+/// Distance distribution of unrelated pairs in a corpus, stratified by lvalue band
+///
+/// Use it to judge how surprising a distance is for files of a given size. The corpus is assumed to
+/// consist of unrelated files, every sampled pair is treated as a chance match.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{NullModel, TLSH};
+/// let corpus = [
+///     TLSH::from_digest("53152333A0D13738E4B172B10F6AC6135BEF7A225664750839D69F8D8E3B6C8D56932C"),
+///     TLSH::from_digest("94052217B1A73B39E46588F54EA5C09C2CFF3F222934210EB1ACA9491F7F7C0955A792"),
+/// ];
+/// let model = NullModel::sample(&corpus, 1000, 0);
+/// let p = model.p_value_of(&corpus[0], &corpus[0]).unwrap();
+/// assert!(p < 0.01);
+/// ```
+#[derive(Clone, Debug)]
+pub struct NullModel {
+    bands: Vec<DistanceHistogram>,
+    pooled: DistanceHistogram,
+}
+
+impl NullModel {
+    /// Builds the model by sampling random pairs of hashes from the same lvalue band
+    ///
+    /// # Arguments
+    ///
+    /// * `corpus` - Hashes of unrelated files
+    /// * `pairs_per_band` - Number of pairs to sample from each band having at least two hashes
+    /// * `seed` - Seed of the pair sampling, the same seed gives the same model
+    pub fn sample(corpus: &[TLSH], pairs_per_band: usize, seed: u64) -> Self {
+        let mut members: Vec<Vec<&TLSH>> = vec![Vec::new(); LVALUE_BANDS];
+        for h in corpus {
+            members[Self::band(h.lvalue)].push(h);
+        }
+
+        let mut rng = SplitMix64::new(seed);
+        let mut bands = vec![DistanceHistogram::new(); LVALUE_BANDS];
+        let mut pooled = DistanceHistogram::new();
+        for (band, hashes) in bands.iter_mut().zip(&members) {
+            if hashes.len() < 2 {
+                continue;
+            }
+            for _ in 0..pairs_per_band {
+                let i = rng.below(hashes.len());
+                let j = (i + 1 + rng.below(hashes.len() - 1)) % hashes.len();
+                let d = TLSH::diff(hashes[i], hashes[j]);
+                band.add(d);
+                pooled.add(d);
+            }
+        }
+        Self { bands, pooled }
+    }
+
+    /// Index of the lvalue band of the given lvalue
+    pub fn band(lvalue: u8) -> usize {
+        (lvalue / LVALUE_BAND_WIDTH) as usize
+    }
+
+    /// Distance histogram of a band
+    pub fn histogram(&self, band: usize) -> &DistanceHistogram {
+        &self.bands[band]
+    }
+
+    /// Distance histogram of all sampled pairs
+    pub fn pooled(&self) -> &DistanceHistogram {
+        &self.pooled
+    }
+
+    /// Empirical p-value of `distance` for files with the given lvalue
+    ///
+    /// Falls back to the pooled histogram if the band of `lvalue` had no samples.
+    /// Returns `None` if the model is empty.
+    pub fn p_value(&self, lvalue: u8, distance: i32) -> Option<f64> {
+        let band = &self.bands[Self::band(lvalue)];
+        if band.total() > 0 {
+            band.p_value(distance)
+        } else {
+            self.pooled.p_value(distance)
+        }
+    }
+
+    /// Empirical p-value of `TLSH::diff(a, b)`, stratified by the lvalue of `a`
+    pub fn p_value_of(&self, a: &TLSH, b: &TLSH) -> Option<f64> {
+        self.p_value(a.lvalue, TLSH::diff(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TLSHBuilder;
+
+    fn random_corpus(count: usize, len: usize, seed: u64) -> Vec<TLSH> {
+        let mut rng = SplitMix64::new(seed);
+        (0..count)
+            .map(|_| {
+                let data = rng.bytes(len);
+                let mut builder = TLSHBuilder::new();
+                builder.update(&data);
+                builder.finalize();
+                builder.get_hash().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut h = DistanceHistogram::new();
+        assert_eq!(h.p_value(10), None);
+        for d in [10, 20, 20, 30] {
+            h.add(d);
+        }
+        assert_eq!(h.total(), 4);
+        assert_eq!(h.count_at_most(19), 1);
+        assert_eq!(h.count_at_most(20), 3);
+        assert_eq!(h.p_value(5), Some(0.2));
+        assert_eq!(h.mean(), Some(20.0));
+        assert_eq!(h.quantile(0.5), Some(20));
+        assert_eq!(h.quantile(1.0), Some(30));
+    }
+
+    #[test]
+    fn test_null_model() {
+        let corpus = random_corpus(40, 2048, 1);
+        let model = NullModel::sample(&corpus, 500, 7);
+        let band = NullModel::band(corpus[0].lvalue);
+        assert_eq!(model.histogram(band).total(), 500);
+        // Unrelated random data is far apart, identical data is not
+        assert!(model.p_value_of(&corpus[0], &corpus[0]).unwrap() < 0.01);
+        assert!(model.p_value_of(&corpus[0], &corpus[1]).unwrap() > 0.01);
+        // Deterministic for a seed
+        let again = NullModel::sample(&corpus, 500, 7);
+        assert_eq!(model.histogram(band).counts(), again.histogram(band).counts());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    hash::{EFF_BUCKETS, TLSH},
+    util::{calc_lvalue, SplitMix64},
+};
+
+/// Largest lvalue produced by real data
+const MAX_LVALUE: u8 = 169;
+
+/// Bucket code pairs to swap, in order of preference, with the distance a swap adds
+const SWAPS: [(u8, u8, i32); 6] = [(0, 3, 12), (0, 2, 4), (1, 3, 4), (0, 1, 2), (1, 2, 2), (2, 3, 2)];
+
+/// Distance added by moving a q-ratio by 0, 1 and 2 steps
+const Q_RATIO_COSTS: [i32; 3] = [0, 1, 12];
+
+/// Generates synthetic TLSH hashes for testing indexes and clustering code
+///
+/// The generated hashes look like hashes of real data: about a quarter of the buckets carry each
+/// code and the lvalue corresponds to a length between 512 bytes and 16 MiB.
+///
+/// # Examples
+///
+/// ```
+/// use ::simbiota_tlsh::{HashGenerator, TLSH};
+/// let mut generator = HashGenerator::new(42);
+/// let base = generator.next_hash();
+/// let near = generator.at_distance(&base, 25).unwrap();
+/// assert_eq!(TLSH::diff(&base, &near), 25);
+/// ```
+#[derive(Clone, Debug)]
+pub struct HashGenerator {
+    rng: SplitMix64,
+}
+
+impl HashGenerator {
+    /// Creates a generator, the same seed gives the same sequence of hashes
+    pub fn new(seed: u64) -> Self {
+        Self { rng: SplitMix64::new(seed) }
+    }
+
+    /// Generates a random, realistic hash
+    pub fn next_hash(&mut self) -> TLSH {
+        let mut buckets = [0u8; EFF_BUCKETS];
+        for (i, b) in buckets.iter_mut().enumerate() {
+            *b = (i * 4 / EFF_BUCKETS) as u8;
+        }
+        self.shuffle(&mut buckets);
+
+        // Log-uniform length between 2^9 and 2^24 bytes
+        let exponent = 9.0 + 15.0 * (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        let lvalue = calc_lvalue(2f64.powf(exponent) as u64).unwrap();
+        TLSH {
+            checksum: self.rng.next_u64() as u8,
+            lvalue,
+            q_ratios: self.rng.next_u64() as u8,
+            codes: pack(&buckets),
+        }
+    }
+
+    /// Generates a hash exactly `distance` away from `base`
+    ///
+    /// Large distances are reached by moving the lvalue, the q-ratios are moved by up to 2 steps
+    /// at random, and the body is changed by swapping bucket codes, so the result keeps the code
+    /// distribution of `base`. An odd rest changes the checksum. Returns `None` if the distance
+    /// cannot be reached from `base`.
+    pub fn at_distance(&mut self, base: &TLSH, distance: i32) -> Option<TLSH> {
+        if distance < 0 {
+            return None;
+        }
+        let buckets = unpack(&base.codes);
+        let mut result = *base;
+        let mut remaining = distance;
+
+        // Whatever the swaps cannot cover is covered by moving the lvalue by 2 or more
+        let body_capacity = self.swap_codes(&buckets, i32::MAX).1 + 1;
+        if remaining > body_capacity {
+            let shift = ((remaining - body_capacity + 11) / 12).max(2);
+            if shift * 12 > remaining || shift > 128 {
+                return None;
+            }
+            let up = base.lvalue as i32 + shift;
+            let down = base.lvalue as i32 - shift;
+            result.lvalue = match (up <= MAX_LVALUE as i32, down >= 0) {
+                (true, true) if self.rng.next_u64() & 1 == 0 => down as u8,
+                (true, _) => up as u8,
+                (_, true) => down as u8,
+                _ => return None,
+            };
+            remaining -= shift * 12;
+        }
+
+        for shift in [0, 4] {
+            let steps = self.rng.below(3) as u8;
+            let cost = Q_RATIO_COSTS[steps as usize];
+            if cost > remaining {
+                continue;
+            }
+            let q_ratio = (base.q_ratios >> shift) & 0xf;
+            let moved = if self.rng.next_u64() & 1 == 0 { q_ratio + steps } else { q_ratio + 16 - steps } & 0xf;
+            result.q_ratios = (result.q_ratios & !(0xf << shift)) | (moved << shift);
+            remaining -= cost;
+        }
+
+        if remaining % 2 == 1 {
+            result.checksum = base.checksum.wrapping_add(1 + self.rng.below(255) as u8);
+            remaining -= 1;
+        }
+
+        let (swapped, covered) = self.swap_codes(&buckets, remaining);
+        if covered != remaining {
+            return None;
+        }
+        result.codes = pack(&swapped);
+        debug_assert_eq!(TLSH::diff(base, &result), distance);
+        Some(result)
+    }
+
+    /// Swaps codes of random bucket pairs until `target` distance is covered or no more swaps fit.
+    ///
+    /// Returns the swapped buckets and the covered distance.
+    fn swap_codes(&mut self, buckets: &[u8; EFF_BUCKETS], target: i32) -> ([u8; EFF_BUCKETS], i32) {
+        let mut unused: [Vec<usize>; 4] = Default::default();
+        let mut order: Vec<usize> = (0..EFF_BUCKETS).collect();
+        self.shuffle(&mut order);
+        for i in order {
+            unused[buckets[i] as usize].push(i);
+        }
+
+        let mut swapped = *buckets;
+        let mut covered = 0;
+        for (a, b, d) in SWAPS {
+            while covered + d <= target {
+                let (Some(i), Some(j)) = (unused[a as usize].last(), unused[b as usize].last()) else {
+                    break;
+                };
+                swapped[*i] = b;
+                swapped[*j] = a;
+                unused[a as usize].pop();
+                unused[b as usize].pop();
+                covered += d;
+            }
+        }
+        (swapped, covered)
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.rng.below(i + 1));
+        }
+    }
+}
+
+fn unpack(codes: &[u8; EFF_BUCKETS / 4]) -> [u8; EFF_BUCKETS] {
+    let mut buckets = [0u8; EFF_BUCKETS];
+    for (i, b) in buckets.iter_mut().enumerate() {
+        *b = (codes[i / 4] >> ((i % 4) * 2)) & 3;
+    }
+    buckets
+}
+
+fn pack(buckets: &[u8; EFF_BUCKETS]) -> [u8; EFF_BUCKETS / 4] {
+    let mut codes = [0u8; EFF_BUCKETS / 4];
+    for (i, b) in buckets.iter().enumerate() {
+        codes[i / 4] |= b << ((i % 4) * 2);
+    }
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mod_diff;
+
+    #[test]
+    fn test_exact_distances() {
+        let mut generator = HashGenerator::new(1);
+        for _ in 0..20 {
+            let base = generator.next_hash();
+            for distance in 0..=600 {
+                let hash = generator.at_distance(&base, distance).unwrap();
+                assert_eq!(TLSH::diff(&base, &hash), distance);
+                assert_eq!(TLSH::try_from_digest(&hash.to_digest()).unwrap().to_digest(), hash.to_digest());
+            }
+        }
+        assert!(generator.at_distance(&generator.clone().next_hash(), -1).is_none());
+    }
+
+    #[test]
+    fn test_q_ratio_perturbations() {
+        let mut generator = HashGenerator::new(3);
+        let base = generator.next_hash();
+        let mut steps_seen = [false; 3];
+        for _ in 0..200 {
+            for distance in [1, 12, 13, 24, 25, 40] {
+                let hash = generator.at_distance(&base, distance).unwrap();
+                assert_eq!(TLSH::diff(&base, &hash), distance);
+                let mut q_cost = 0;
+                for shift in [0, 4] {
+                    let steps = mod_diff(((base.q_ratios >> shift) & 0xf) as u32, ((hash.q_ratios >> shift) & 0xf) as u32, 16);
+                    assert!(steps <= 2);
+                    steps_seen[steps as usize] = true;
+                    q_cost += Q_RATIO_COSTS[steps as usize];
+                }
+                let rest = TLSH { q_ratios: base.q_ratios, ..hash };
+                assert_eq!(TLSH::diff(&base, &rest), distance - q_cost);
+            }
+        }
+        assert_eq!(steps_seen, [true; 3]);
+    }
+
+    #[test]
+    fn test_realistic_hashes() {
+        let mut a = HashGenerator::new(5);
+        let mut b = HashGenerator::new(5);
+        for _ in 0..100 {
+            let hash = a.next_hash();
+            assert_eq!(hash.to_digest(), b.next_hash().to_digest());
+            assert!(hash.lvalue <= MAX_LVALUE);
+            let buckets = unpack(&hash.codes);
+            for code in 0..4 {
+                assert_eq!(buckets.iter().filter(|b| **b == code).count(), EFF_BUCKETS / 4);
+            }
+        }
+    }
+}
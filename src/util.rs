@@ -16,9 +16,17 @@ const V_TABLE: [u8; 256] = [
     194, 139, 112, 43, 71, 109, 184, 209,
 ];
 
+/// Salts of the six bucket mappings of a window
+pub const SALTS: [u8; 6] = [2, 3, 5, 7, 11, 13];
+
+#[derive(Clone)]
 pub struct Pearson {
     pub color: u8,
+    /// Key id of a keyed color
+    pub key_id: Option<u32>,
     v_table: [u8; 256],
+    /// First two mapping steps for each salt: `salted[s][i] == v_table[v_table[SALTS[s]] ^ i]`
+    salted: [[u8; 256]; SALTS.len()],
 }
 
 impl Pearson {
@@ -30,7 +38,32 @@ impl Pearson {
                 v_table[i] = V_TABLE[v_table[i] as usize];
             }
         }
-        Pearson { color, v_table }
+        Self::with_table(color, v_table)
+    }
+
+    /// A permutation shuffled by SipHash-2-4 of `key`, for a keyed color with the given marker
+    ///
+    /// A Fisher-Yates shuffle, drawing the position of the `i`th element from the hash of the
+    /// marker and `i`. Different markers give unrelated permutations for the same key.
+    pub fn keyed(key: &[u8; 16], marker: u8) -> Self {
+        let mut v_table: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for i in (1..256usize).rev() {
+            let r = siphash24(key, &[b'T', b'L', b'S', b'H', marker, i as u8]);
+            let j = ((r as u128 * (i as u128 + 1)) >> 64) as usize;
+            v_table.swap(i, j);
+        }
+        Self::with_table(marker, v_table)
+    }
+
+    fn with_table(color: u8, v_table: [u8; 256]) -> Self {
+        let mut salted = [[0; 256]; SALTS.len()];
+        for (table, salt) in salted.iter_mut().zip(SALTS) {
+            let mapped_salt = v_table[salt as usize];
+            for (i, t) in table.iter_mut().enumerate() {
+                *t = v_table[(mapped_salt ^ i as u8) as usize];
+            }
+        }
+        Pearson { color, key_id: None, v_table, salted }
     }
 
     #[allow(dead_code)]
@@ -42,6 +75,7 @@ impl Pearson {
         h
     }
 
+    #[cfg(test)]
     pub fn fast_b_mapping(&self, s: u8, i: u8, j: u8, k: u8) -> u8 {
         let h = self.v_table[s as usize];
         let h = self.v_table[(h ^ i) as usize];
@@ -54,6 +88,58 @@ impl Pearson {
         let h = self.v_table[(h ^ j) as usize];
         self.v_table[(h ^ k) as usize]
     }
+
+    /// Equivalent to `fast_b_mapping(SALTS[salt], i, j, k)` using the precomputed salted table
+    #[inline(always)]
+    pub fn salted_b_mapping(&self, salt: usize, i: u8, j: u8, k: u8) -> u8 {
+        let h = self.salted[salt][i as usize];
+        let h = self.v_table[(h ^ j) as usize];
+        self.v_table[(h ^ k) as usize]
+    }
+}
+
+/// SipHash-2-4 of `data` with a 128-bit key
+pub fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+    fn compress(v: &mut [u64; 4], m: u64) {
+        v[3] ^= m;
+        round(v);
+        round(v);
+        v[0] ^= m;
+    }
+
+    let k0 = u64::from_le_bytes(key[..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(key[8..].try_into().unwrap());
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        compress(&mut v, u64::from_le_bytes(block.try_into().unwrap()));
+    }
+    let mut last = [0; 8];
+    last[..blocks.remainder().len()].copy_from_slice(blocks.remainder());
+    last[7] = data.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last));
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
 }
 
 impl Default for Pearson {
@@ -81,11 +167,27 @@ const TOPVAL: [u32; 170] = [
     2622945920, 2885240448, 3173764736, 3491141248, 3840255616, 4224281216,
 ];
 
-pub fn calc_lvalue(length: u32) -> Option<u8> {
+/// Shortest data length a hash is calculated for by default
+pub const MIN_DATA_LENGTH: u64 = 50;
+/// Shortest data length a hash is calculated for in conservative mode
+pub const MIN_CONSERVATIVE_DATA_LENGTH: u64 = 256;
+/// Longest data length an lvalue can represent
+pub const MAX_DATA_LENGTH: u64 = TOPVAL[TOPVAL.len() - 1] as u64;
+
+pub fn calc_lvalue(length: u64) -> Option<u8> {
+    if length < MIN_DATA_LENGTH {
+        return None;
+    }
+    lvalue_of(length)
+}
+
+/// The lvalue of any length up to the largest representable one
+pub fn lvalue_of(length: u64) -> Option<u8> {
     // bisect_left(&topval, &length)
-    if length < 50 || length > *TOPVAL.last().unwrap() {
+    if length > MAX_DATA_LENGTH {
         return None;
     }
+    let length = length as u32;
     let mut lo: usize = 0;
     let mut hi: usize = TOPVAL.len() - 1;
     while lo != hi {
@@ -107,6 +209,58 @@ pub fn mod_diff(a: u32, b: u32, m: u32) -> u32 {
     }
 }
 
+/// A small, seedable SplitMix64 generator
+///
+/// Used wherever the crate needs reproducible pseudo-random choices (sampling, synthetic data).
+/// It is not suitable for anything security related.
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a uniformly distributed number in `0..bound`
+    pub fn below(&mut self, bound: usize) -> usize {
+        assert!(bound > 0, "Empty range");
+        ((self.next_u64() as u128 * bound as u128) >> 64) as usize
+    }
+
+    /// Returns `len` uniformly distributed bytes
+    #[cfg(test)]
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// Returns `len` random bytes over a random alphabet of 24 byte values of random weights
+    #[cfg(test)]
+    pub fn weighted(&mut self, len: usize) -> Vec<u8> {
+        let symbols = self.bytes(24);
+        let table: Vec<u8> = symbols.iter().flat_map(|b| vec![*b; 1 + self.below(10)]).collect();
+        (0..len).map(|_| table[self.below(table.len())]).collect()
+    }
+
+    /// Overwrites `count` random positions of `data` with random bytes
+    #[cfg(test)]
+    pub fn mutate(&mut self, data: &mut [u8], count: usize) {
+        for _ in 0..count {
+            let i = self.below(data.len());
+            data[i] = self.next_u64() as u8;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,5 +275,45 @@ mod tests {
         assert_eq!(Some(169), calc_lvalue(4224281216));
         assert_eq!(None, calc_lvalue(4224281217));
         assert_eq!(None, calc_lvalue(4294967295));
+        // Would be 5_000_000_000 - 2^32, within the range, if truncated to 32 bits
+        assert_eq!(None, calc_lvalue(5_000_000_000));
+        assert_eq!(None, calc_lvalue(u64::MAX));
+        assert_eq!(Some(0), lvalue_of(0));
+        assert_eq!(Some(5), lvalue_of(11));
+        assert_eq!(None, lvalue_of(4224281217));
+    }
+
+    #[test]
+    fn siphash_test() {
+        // Test vectors of the SipHash paper, key 00..0f and messages 00..len-1
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let message: Vec<u8> = (0..16).collect();
+        assert_eq!(siphash24(&key, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(&key, &message[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash24(&key, &message[..15]), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn keyed_permutation_test() {
+        let a = Pearson::keyed(&[1; 16], 0x10);
+        let mut sorted = a.v_table;
+        sorted.sort_unstable();
+        assert!(sorted.iter().enumerate().all(|(i, v)| i == *v as usize));
+        assert_eq!(a.v_table, Pearson::keyed(&[1; 16], 0x10).v_table);
+        assert_ne!(a.v_table, Pearson::keyed(&[2; 16], 0x10).v_table);
+        assert_ne!(a.v_table, Pearson::keyed(&[1; 16], 0x11).v_table);
+    }
+
+    #[test]
+    fn salted_mapping_test() {
+        let mut rng = SplitMix64::new(0);
+        for pearson in (0..16).map(Pearson::new).chain([Pearson::keyed(&[7; 16], 0x42)]) {
+            for (s, salt) in SALTS.iter().enumerate() {
+                for _ in 0..1000 {
+                    let (i, j, k) = (rng.next_u64() as u8, rng.next_u64() as u8, rng.next_u64() as u8);
+                    assert_eq!(pearson.salted_b_mapping(s, i, j, k), pearson.fast_b_mapping(*salt, i, j, k));
+                }
+            }
+        }
     }
 }